@@ -1,45 +1,79 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::time::Instant;
 
-fn read_boarding_passes() -> Result<Vec<usize>> {
+mod simulator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Seat {
+    row: usize,
+    column: usize,
+}
+
+impl Seat {
+    fn new(row: usize, column: usize) -> Self {
+        Self { row, column }
+    }
+
+    fn decode(pass: &str) -> Result<Self> {
+        if pass.len() != 10 {
+            bail!("Invalid boarding pass {:?}", pass);
+        }
+        let bits = pass
+            .char_indices()
+            .map(|(i, c)| {
+                let n = match (i, c) {
+                    (0..=6, 'F') => 0,
+                    (0..=6, 'B') => 1,
+                    (7..=9, 'L') => 0,
+                    (7..=9, 'R') => 1,
+                    _ => bail!("Invalid character {} in {:?}", c, pass),
+                };
+                if i < 7 {
+                    Ok(n << (6 - i))
+                } else {
+                    Ok(n << (9 - i))
+                }
+            })
+            .collect::<Result<Vec<usize>>>()?;
+        let row: usize = bits[0..7].iter().sum();
+        let column: usize = bits[7..10].iter().sum();
+        Ok(Self::new(row, column))
+    }
+
+    fn encode(&self) -> String {
+        let row = (0..7).rev().map(|i| match (self.row >> i) & 1 {
+            0 => 'F',
+            _ => 'B',
+        });
+        let column = (0..3).rev().map(|i| match (self.column >> i) & 1 {
+            0 => 'L',
+            _ => 'R',
+        });
+        row.chain(column).collect()
+    }
+
+    fn id(&self) -> usize {
+        self.row * 8 + self.column
+    }
+}
+
+fn read_boarding_passes() -> Result<Vec<Seat>> {
     let f = File::open("input.txt")?;
     let reader = BufReader::new(f);
 
-    let seat_ids: Vec<usize> = reader
+    let seats: Vec<Seat> = reader
         .lines()
-        .map(|line| line.unwrap())
-        .map(|line| {
-            line.char_indices()
-                .map(|(i, c)| {
-                    let n = match c {
-                        'F' => 0,
-                        'B' => 1,
-                        'L' => 0,
-                        'R' => 1,
-                        _ => panic!("Invalid character {}", c),
-                    };
-                    if i < 7 {
-                        n << (6 - i)
-                    } else {
-                        n << (9 - i)
-                    }
-                })
-                .collect::<Vec<usize>>()
-        })
-        .map(|bits| {
-            let row: usize = bits[0..7].iter().sum();
-            let column: usize = bits[7..10].iter().sum();
-            row * 8 + column
-        })
-        .collect();
-
-    Ok(seat_ids)
+        .map(|line| Seat::decode(&line?))
+        .collect::<Result<_>>()?;
+
+    Ok(seats)
 }
 
-fn find_gap(seat_ids: &[usize], max_id: usize) -> usize {
+fn find_gap(seat_ids: &[usize], max_id: usize) -> Option<usize> {
     let mut register = vec![false; max_id + 1];
     for id in seat_ids {
         register[*id] = true;
@@ -47,8 +81,8 @@ fn find_gap(seat_ids: &[usize], max_id: usize) -> usize {
 
     let mut prev: Option<usize> = None;
     let mut gap: Option<usize> = None;
-    for i in 0..max_id + 1 {
-        match (prev, gap, register[i]) {
+    for (i, &taken) in register.iter().enumerate() {
+        match (prev, gap, taken) {
             (None, None, false) => (),
             (None, None, true) => prev = Some(i),
             (Some(_), None, false) => gap = Some(i),
@@ -57,23 +91,50 @@ fn find_gap(seat_ids: &[usize], max_id: usize) -> usize {
                 prev = None;
                 gap = None;
             }
-            (Some(_), Some(g), true) => return g,
-            _ => panic!("Invalid state {:?} {:?} {:?}", prev, gap, register[i]),
+            (Some(_), Some(g), true) => return Some(g),
+            _ => panic!("Invalid state {:?} {:?} {:?}", prev, gap, taken),
         }
     }
-    panic!("Gap index not found")
+    None
 }
 
 fn main() -> Result<()> {
     let start = Instant::now();
 
-    let seat_ids = read_boarding_passes()?;
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("simulate") {
+        simulator::run(&args[1..])?;
+        println!("Finished in {} us", start.elapsed().as_micros());
+        return Ok(());
+    }
+
+    let seat_ids: Vec<usize> = read_boarding_passes()?.iter().map(Seat::id).collect();
     let max_id = seat_ids.iter().max().unwrap();
 
     println!("Highest seat ID: {}", max_id);
-    let gap = find_gap(&seat_ids, *max_id);
+    let gap = find_gap(&seat_ids, *max_id).expect("Gap index not found");
     println!("gap: {}", gap);
 
     println!("Finished in {} us", start.elapsed().as_micros());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let seat = Seat::decode("FBFBBFFRLR").unwrap();
+        assert_eq!(seat, Seat::new(44, 5));
+        assert_eq!(seat.id(), 357);
+        assert!(Seat::decode("FBFBBFFRLX").is_err());
+        assert!(Seat::decode("FBFBBFFLRLR").is_err());
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(Seat::new(70, 7).encode(), "BFFFBBFRRR");
+        assert_eq!(Seat::decode("BBFFBBFRLL").unwrap().encode(), "BBFFBBFRLL");
+    }
+}
@@ -0,0 +1,638 @@
+use crate::{find_gap, Seat};
+use anyhow::{anyhow, bail, Result};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+// The boarding pass encoding has 7 row bits and 3 column bits.
+const MAX_ROWS: usize = 128;
+const MAX_COLUMNS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Preference {
+    Any,
+    Window,
+    Aisle,
+}
+
+#[derive(Debug, Clone)]
+struct Passenger {
+    group_size: usize,
+    preference: Preference,
+    rows: Option<(usize, usize)>,
+}
+
+impl Passenger {
+    /// Parses a line of the form `<group size> <any|window|aisle> [<first row>-<last row>]`.
+    fn parse(line: &str) -> Result<Self> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (size, preference, rows) = match &parts[..] {
+            [size, preference] => (size, preference, None),
+            [size, preference, rows] => (size, preference, Some(rows)),
+            _ => bail!("Invalid passenger {:?}", line),
+        };
+        let group_size = size.parse::<usize>()?;
+        if group_size == 0 {
+            bail!("Empty group in {:?}", line);
+        }
+        let preference = match *preference {
+            "any" => Preference::Any,
+            "window" => Preference::Window,
+            "aisle" => Preference::Aisle,
+            _ => bail!("Invalid preference {:?}", preference),
+        };
+        let rows = match rows {
+            None => None,
+            Some(range) => {
+                let (first, last) = range
+                    .split_once('-')
+                    .ok_or_else(|| anyhow!("Invalid row range {:?}", range))?;
+                let (first, last) = (first.parse::<usize>()?, last.parse::<usize>()?);
+                if first > last {
+                    bail!("Empty row range {:?}", range);
+                }
+                Some((first, last))
+            }
+        };
+        Ok(Self {
+            group_size,
+            preference,
+            rows,
+        })
+    }
+
+    fn accepts_row(&self, row: usize) -> bool {
+        match self.rows {
+            None => true,
+            Some((first, last)) => (first..=last).contains(&row),
+        }
+    }
+}
+
+/// Tiny xorshift generator, good enough for synthetic passenger loads.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+fn generate_passengers(count: usize, seed: u64, layout: &Layout) -> Vec<Passenger> {
+    let mut rng = Rng(seed.max(1));
+    let mut passengers = Vec::new();
+    let mut seated = 0;
+    while seated < count {
+        let group_size = match rng.next(10) {
+            0..=5 => 1,
+            6..=8 => 2,
+            _ => 3,
+        }
+        .min(count - seated);
+        let preference = match rng.next(3) {
+            0 => Preference::Any,
+            1 => Preference::Window,
+            _ => Preference::Aisle,
+        };
+        let rows = match rng.next(3) {
+            0 => {
+                let first = rng.next(layout.rows);
+                Some((first, (first + 10).min(layout.rows - 1)))
+            }
+            _ => None,
+        };
+        passengers.push(Passenger {
+            group_size,
+            preference,
+            rows,
+        });
+        seated += group_size;
+    }
+    passengers
+}
+
+#[derive(Debug, Clone)]
+struct Layout {
+    rows: usize,
+    blocks: Vec<usize>,
+}
+
+impl Layout {
+    /// Parses block widths separated by aisles, e.g. `2-4-2`.
+    fn parse(rows: usize, blocks: &str) -> Result<Self> {
+        let blocks = blocks
+            .split('-')
+            .map(|width| width.parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()?;
+        let layout = Self { rows, blocks };
+        if layout.rows == 0 || layout.rows > MAX_ROWS {
+            bail!("Row count must be between 1 and {}", MAX_ROWS);
+        }
+        if layout.blocks.contains(&0) || layout.columns() > MAX_COLUMNS {
+            bail!("Seat blocks must fit in {} columns", MAX_COLUMNS);
+        }
+        Ok(layout)
+    }
+
+    fn columns(&self) -> usize {
+        self.blocks.iter().sum()
+    }
+
+    fn is_window(&self, column: usize) -> bool {
+        column == 0 || column == self.columns() - 1
+    }
+
+    fn is_aisle(&self, column: usize) -> bool {
+        let mut start = 0;
+        for width in &self.blocks {
+            let end = start + width - 1;
+            if (column == start || column == end) && !self.is_window(column) {
+                return true;
+            }
+            start += width;
+        }
+        false
+    }
+
+    fn matches(&self, column: usize, preference: Preference) -> bool {
+        match preference {
+            Preference::Any => true,
+            Preference::Window => self.is_window(column),
+            Preference::Aisle => self.is_aisle(column),
+        }
+    }
+
+    /// All runs of `size` adjacent columns that do not cross an aisle.
+    fn runs(&self, size: usize) -> Vec<usize> {
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for width in &self.blocks {
+            if size <= *width {
+                runs.extend(start..=start + width - size);
+            }
+            start += width;
+        }
+        runs
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    Greedy,
+    /// Seats groups greedily, largest first, then matches single passengers to
+    /// seats so that as many of their preferences as possible are satisfied.
+    MatchSingles,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    passengers: usize,
+    seated: usize,
+    rejected: usize,
+    split_groups: usize,
+    preferences: usize,
+    satisfied: usize,
+}
+
+struct Simulation {
+    layout: Layout,
+    occupied: Vec<Vec<bool>>,
+    assignments: Vec<Vec<Seat>>,
+    stats: Stats,
+}
+
+impl Simulation {
+    fn new(layout: Layout) -> Self {
+        Self {
+            occupied: vec![vec![false; layout.columns()]; layout.rows],
+            layout,
+            assignments: Vec::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    fn take(&mut self, seats: &[Seat]) {
+        for seat in seats {
+            self.occupied[seat.row][seat.column] = true;
+        }
+    }
+
+    /// Finds `passenger.group_size` adjacent free seats. Without
+    /// `respect_preference`, the seat type and row range are ignored.
+    fn find_run(&self, passenger: &Passenger, respect_preference: bool) -> Option<Vec<Seat>> {
+        let size = passenger.group_size;
+        let rows =
+            (0..self.layout.rows).filter(|row| !respect_preference || passenger.accepts_row(*row));
+        for row in rows {
+            for start in self.layout.runs(size) {
+                let columns = start..start + size;
+                if columns.clone().any(|column| self.occupied[row][column]) {
+                    continue;
+                }
+                if respect_preference
+                    && !columns
+                        .clone()
+                        .any(|column| self.layout.matches(column, passenger.preference))
+                {
+                    continue;
+                }
+                return Some(columns.map(|column| Seat::new(row, column)).collect());
+            }
+        }
+        None
+    }
+
+    fn free_seats(&self, passenger: &Passenger) -> Vec<Seat> {
+        let mut seats = Vec::new();
+        for row in 0..self.layout.rows {
+            for column in 0..self.layout.columns() {
+                if !self.occupied[row][column] {
+                    seats.push(Seat::new(row, column));
+                }
+            }
+        }
+        // Seats in the requested rows come first, the rest of the plane after.
+        seats.sort_by_key(|seat| !passenger.accepts_row(seat.row));
+        seats
+    }
+
+    /// Seats a group next to each other if possible, and scattered otherwise.
+    fn seat_greedy(&mut self, passenger: &Passenger) -> Vec<Seat> {
+        let respected = passenger.preference != Preference::Any || passenger.rows.is_some();
+        if let Some(seats) = self.find_run(passenger, true) {
+            self.stats.satisfied += respected as usize;
+            self.take(&seats);
+            return seats;
+        }
+        let seats = match self.find_run(passenger, false) {
+            Some(seats) => seats,
+            None => {
+                let seats: Vec<Seat> = self
+                    .free_seats(passenger)
+                    .into_iter()
+                    .take(passenger.group_size)
+                    .collect();
+                if passenger.group_size > 1 && !seats.is_empty() {
+                    self.stats.split_groups += 1;
+                }
+                seats
+            }
+        };
+        self.take(&seats);
+        seats
+    }
+
+    fn seat_all(&mut self, passengers: &[Passenger], strategy: Strategy) {
+        self.assignments = vec![Vec::new(); passengers.len()];
+        match strategy {
+            Strategy::Greedy => {
+                for (i, passenger) in passengers.iter().enumerate() {
+                    self.assignments[i] = self.seat_greedy(passenger);
+                }
+            }
+            Strategy::MatchSingles => {
+                // Groups are the hard constraint, so they go first, largest first.
+                let mut groups: Vec<usize> = (0..passengers.len())
+                    .filter(|i| passengers[*i].group_size > 1)
+                    .collect();
+                groups.sort_by_key(|i| std::cmp::Reverse(passengers[*i].group_size));
+                for i in groups {
+                    self.assignments[i] = self.seat_greedy(&passengers[i]);
+                }
+                let singles: Vec<usize> = (0..passengers.len())
+                    .filter(|i| passengers[*i].group_size == 1)
+                    .collect();
+                self.match_singles(passengers, &singles);
+            }
+        }
+
+        for (passenger, seats) in passengers.iter().zip(&self.assignments) {
+            self.stats.passengers += passenger.group_size;
+            self.stats.seated += seats.len();
+            self.stats.rejected += passenger.group_size - seats.len();
+            if passenger.preference != Preference::Any || passenger.rows.is_some() {
+                self.stats.preferences += 1;
+            }
+        }
+    }
+
+    /// Maximum bipartite matching between single passengers and the free seats
+    /// that satisfy their preference. Whoever stays unmatched is seated greedily.
+    fn match_singles(&mut self, passengers: &[Passenger], singles: &[usize]) {
+        let columns = self.layout.columns();
+        let constrained: Vec<usize> = singles
+            .iter()
+            .copied()
+            .filter(|i| {
+                passengers[*i].preference != Preference::Any || passengers[*i].rows.is_some()
+            })
+            .collect();
+        let candidates: Vec<Vec<usize>> = constrained
+            .iter()
+            .map(|i| {
+                let passenger = &passengers[*i];
+                (0..self.layout.rows)
+                    .filter(|row| passenger.accepts_row(*row))
+                    .flat_map(|row| (0..columns).map(move |column| (row, column)))
+                    .filter(|(row, column)| {
+                        !self.occupied[*row][*column]
+                            && self.layout.matches(*column, passenger.preference)
+                    })
+                    .map(|(row, column)| row * columns + column)
+                    .collect()
+            })
+            .collect();
+
+        let mut seat_owner: Vec<Option<usize>> = vec![None; self.layout.rows * columns];
+        for p in 0..constrained.len() {
+            let mut visited = vec![false; seat_owner.len()];
+            augment(p, &candidates, &mut seat_owner, &mut visited);
+        }
+
+        for (seat, owner) in seat_owner.iter().enumerate() {
+            if let Some(p) = owner {
+                let seat = Seat::new(seat / columns, seat % columns);
+                self.take(&[seat]);
+                self.assignments[constrained[*p]] = vec![seat];
+                self.stats.satisfied += 1;
+            }
+        }
+        for i in singles {
+            if self.assignments[*i].is_empty() {
+                let passenger = &passengers[*i];
+                self.assignments[*i] = self.free_seats(passenger).into_iter().take(1).collect();
+                let seats = self.assignments[*i].clone();
+                self.take(&seats);
+            }
+        }
+    }
+
+    fn report(&self) {
+        let occupied = |filter: &dyn Fn(usize) -> bool| {
+            let seats = self.occupied.iter().flatten().enumerate();
+            let mut taken = 0;
+            let mut count = 0;
+            for (i, is_taken) in seats {
+                if filter(i % self.layout.columns()) {
+                    count += 1;
+                    taken += *is_taken as usize;
+                }
+            }
+            (taken, count)
+        };
+        let percent = |(taken, count): (usize, usize)| {
+            format!(
+                "{}/{} ({:.1}%)",
+                taken,
+                count,
+                100.0 * taken as f64 / count.max(1) as f64
+            )
+        };
+
+        println!("seats filled: {}", percent(occupied(&|_| true)));
+        println!(
+            "window seats filled: {}",
+            percent(occupied(&|c| self.layout.is_window(c)))
+        );
+        println!(
+            "aisle seats filled: {}",
+            percent(occupied(&|c| self.layout.is_aisle(c)))
+        );
+        println!(
+            "passengers seated: {}/{}, rejected: {}",
+            self.stats.seated, self.stats.passengers, self.stats.rejected
+        );
+        println!("groups split up: {}", self.stats.split_groups);
+        println!(
+            "preferences satisfied: {}/{}",
+            self.stats.satisfied, self.stats.preferences
+        );
+    }
+
+    fn seat_ids(&self) -> Vec<usize> {
+        self.assignments.iter().flatten().map(Seat::id).collect()
+    }
+}
+
+fn augment(
+    p: usize,
+    candidates: &[Vec<usize>],
+    seat_owner: &mut [Option<usize>],
+    visited: &mut [bool],
+) -> bool {
+    for &seat in &candidates[p] {
+        if visited[seat] {
+            continue;
+        }
+        visited[seat] = true;
+        let free = match seat_owner[seat] {
+            None => true,
+            Some(other) => augment(other, candidates, seat_owner, visited),
+        };
+        if free {
+            seat_owner[seat] = Some(p);
+            return true;
+        }
+    }
+    false
+}
+
+fn read_passengers(path: &str) -> Result<Vec<Passenger>> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+
+    let mut passengers = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        passengers.push(Passenger::parse(&line)?);
+    }
+    Ok(passengers)
+}
+
+/// Lists empty seats whose neighbouring IDs are both occupied, i.e. the seats
+/// `find_gap` is looking for.
+fn isolated_gaps(seat_ids: &[usize]) -> Vec<usize> {
+    let max_id = seat_ids.iter().copied().max().unwrap_or(0);
+    let mut register = vec![false; max_id + 2];
+    for id in seat_ids {
+        register[*id] = true;
+    }
+    (1..=max_id)
+        .filter(|i| !register[*i] && register[i - 1] && register[i + 1])
+        .collect()
+}
+
+/// Entry point for `day5 simulate [FILE] [--match-singles] [--layout 2-4-2] [--rows N]
+/// [--random N] [--seed S] [--output FILE]`.
+pub fn run(args: &[String]) -> Result<()> {
+    let mut file = None;
+    let mut strategy = Strategy::Greedy;
+    let mut blocks = String::from("2-4-2");
+    let mut rows = MAX_ROWS;
+    let mut random = None;
+    let mut seed = 2020;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--match-singles" => strategy = Strategy::MatchSingles,
+            "--greedy" => strategy = Strategy::Greedy,
+            "--layout" => blocks = value()?.clone(),
+            "--rows" => rows = value()?.parse()?,
+            "--random" => random = Some(value()?.parse::<usize>()?),
+            "--seed" => seed = value()?.parse()?,
+            "--output" => output = Some(value()?.clone()),
+            path if !path.starts_with("--") => file = Some(path.to_owned()),
+            _ => bail!("Unknown option {}", arg),
+        }
+    }
+
+    let layout = Layout::parse(rows, &blocks)?;
+    let passengers = match (file, random) {
+        (Some(path), None) => read_passengers(&path)?,
+        (None, Some(count)) => generate_passengers(count, seed, &layout),
+        _ => bail!("Pass either a passenger file or --random N"),
+    };
+    for passenger in &passengers {
+        if let Some((first, last)) = passenger.rows {
+            if first >= layout.rows {
+                bail!(
+                    "Row range {}-{} is outside the plane's {} rows",
+                    first,
+                    last,
+                    layout.rows
+                );
+            }
+        }
+    }
+
+    let mut simulation = Simulation::new(layout);
+    simulation.seat_all(&passengers, strategy);
+
+    let passes: Vec<String> = simulation
+        .assignments
+        .iter()
+        .flatten()
+        .map(Seat::encode)
+        .collect();
+    match output {
+        Some(path) => {
+            let mut file = File::create(path)?;
+            for pass in &passes {
+                writeln!(file, "{}", pass)?;
+            }
+        }
+        None => passes.iter().for_each(|pass| println!("{}", pass)),
+    }
+
+    simulation.report();
+
+    let seat_ids = simulation.seat_ids();
+    let gaps = isolated_gaps(&seat_ids);
+    let max_id = seat_ids.iter().copied().max().unwrap_or(0);
+    println!("isolated gaps: {:?}", gaps);
+    println!("find_gap: {:?}", find_gap(&seat_ids, max_id));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passenger(line: &str) -> Passenger {
+        Passenger::parse(line).unwrap()
+    }
+
+    #[test]
+    fn layout() {
+        let layout = Layout::parse(10, "2-4-2").unwrap();
+        assert_eq!(layout.columns(), 8);
+        let windows: Vec<usize> = (0..8).filter(|c| layout.is_window(*c)).collect();
+        assert_eq!(windows, vec![0, 7]);
+        let aisles: Vec<usize> = (0..8).filter(|c| layout.is_aisle(*c)).collect();
+        assert_eq!(aisles, vec![1, 2, 5, 6]);
+        assert_eq!(layout.runs(3), vec![2, 3]);
+        assert!(Layout::parse(10, "4-5").is_err());
+    }
+
+    #[test]
+    fn greedy_groups_stay_together() {
+        let mut simulation = Simulation::new(Layout::parse(2, "3-3").unwrap());
+        let passengers = vec![passenger("3 window 1-1"), passenger("2 aisle")];
+        simulation.seat_all(&passengers, Strategy::Greedy);
+        assert_eq!(
+            simulation.assignments[0],
+            vec![Seat::new(1, 0), Seat::new(1, 1), Seat::new(1, 2)]
+        );
+        assert_eq!(
+            simulation.assignments[1],
+            vec![Seat::new(0, 1), Seat::new(0, 2)]
+        );
+        assert_eq!(simulation.stats.satisfied, 2);
+    }
+
+    #[test]
+    fn matching_beats_greedy() {
+        // The first passenger takes the only window seat in row 0 under the greedy
+        // strategy, leaving the row-restricted second passenger without one.
+        let passengers = vec![passenger("1 window"), passenger("1 window 0-0")];
+        let layout = Layout::parse(2, "1").unwrap();
+
+        let mut greedy = Simulation::new(layout.clone());
+        greedy.seat_all(&passengers, Strategy::Greedy);
+        assert_eq!(greedy.stats.satisfied, 1);
+
+        let mut matched = Simulation::new(layout);
+        matched.seat_all(&passengers, Strategy::MatchSingles);
+        assert_eq!(matched.stats.satisfied, 2);
+        assert_eq!(matched.assignments[1], vec![Seat::new(0, 0)]);
+    }
+
+    #[test]
+    fn groups_leave_their_rows_before_splitting() {
+        let mut simulation = Simulation::new(Layout::parse(2, "3").unwrap());
+        let passengers = vec![passenger("1 any 0-0"), passenger("3 any 0-0")];
+        simulation.seat_all(&passengers, Strategy::Greedy);
+        assert_eq!(
+            simulation.assignments[1],
+            vec![Seat::new(1, 0), Seat::new(1, 1), Seat::new(1, 2)]
+        );
+        assert_eq!(simulation.stats.split_groups, 0);
+        assert_eq!(simulation.stats.satisfied, 1);
+    }
+
+    #[test]
+    fn full_plane_rejects() {
+        let mut simulation = Simulation::new(Layout::parse(1, "2").unwrap());
+        simulation.seat_all(&[passenger("3 any")], Strategy::Greedy);
+        assert_eq!(simulation.stats.seated, 2);
+        assert_eq!(simulation.stats.rejected, 1);
+        assert_eq!(simulation.stats.split_groups, 1);
+    }
+
+    #[test]
+    fn row_ranges() {
+        assert_eq!(passenger("1 any 3-5").rows, Some((3, 5)));
+        assert_eq!(passenger("1 any 4-4").rows, Some((4, 4)));
+        assert!(Passenger::parse("1 any 5-3").is_err());
+        assert!(Passenger::parse("1 any -3").is_err());
+        assert!(Passenger::parse("1 any 3-").is_err());
+    }
+
+    #[test]
+    fn gaps() {
+        assert_eq!(isolated_gaps(&[3, 4, 6, 7, 9]), vec![5, 8]);
+    }
+}
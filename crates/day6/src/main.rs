@@ -1,12 +1,220 @@
-use anyhow::Result;
-use std::collections::HashSet;
-use std::fs::File;
+use anyhow::{anyhow, bail, Result};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::ops::Range;
 use std::time::Instant;
 
-fn get_groups_union() -> Result<Vec<HashSet<char>>> {
-    let file = File::open("input.txt")?;
+/// One bit per question of the alphabet, one mask per person.
+type Answers = u128;
+
+/// The questions a survey can contain. By default these are the letters `a`..=`z`,
+/// but newer forms declare their own codes such as `Q12`.
+struct Alphabet {
+    tokens: Vec<String>,
+    index: HashMap<String, usize>,
+    ascii: [Option<u8>; 128],
+}
+
+impl Alphabet {
+    fn new(tokens: Vec<String>) -> Result<Self> {
+        if tokens.len() > Answers::BITS as usize {
+            bail!(
+                "alphabet has {} questions, at most {} are supported",
+                tokens.len(),
+                Answers::BITS
+            );
+        }
+        let mut index = HashMap::new();
+        let mut ascii = [None; 128];
+        for (i, token) in tokens.iter().enumerate() {
+            if index.insert(token.clone(), i).is_some() {
+                bail!("duplicate question {:?} in alphabet", token);
+            }
+            if token.len() == 1 && token.is_ascii() {
+                ascii[token.as_bytes()[0] as usize] = Some(i as u8);
+            }
+        }
+        Ok(Self {
+            tokens,
+            index,
+            ascii,
+        })
+    }
+
+    fn letters() -> Self {
+        Self::new(('a'..='z').map(String::from).collect()).unwrap()
+    }
+
+    /// Parses a declaration of comma- or space-separated question codes.
+    fn parse(text: &str) -> Result<Self> {
+        Self::new(split_tokens(text).map(String::from).collect())
+    }
+
+    fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    fn get(&self, token: &str) -> Option<usize> {
+        match token.as_bytes() {
+            [c] if c.is_ascii() => self.ascii[*c as usize].map(usize::from),
+            _ => self.index.get(token).copied(),
+        }
+    }
+}
+
+fn split_tokens(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+}
+
+/// How a line is split into answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Every character is an answer.
+    Chars,
+    /// Answers are comma- or space-separated tokens.
+    Tokens,
+}
+
+/// All groups of a survey dump, parsed once. Persons are stored back to back and
+/// each group is a range into `answers`.
+struct Survey {
+    alphabet: Alphabet,
+    answers: Vec<Answers>,
+    groups: Vec<Range<usize>>,
+}
+
+impl Survey {
+    fn parse(text: &str, alphabet: Alphabet, mode: Mode) -> Result<Self> {
+        let mut answers = Vec::new();
+        let mut groups = Vec::new();
+        let mut group_start = 0;
+
+        for (line_nr, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                if answers.len() > group_start {
+                    groups.push(group_start..answers.len());
+                }
+                group_start = answers.len();
+                continue;
+            }
+            let mut mask: Answers = 0;
+            let unknown = |token: &str| anyhow!("line {}: unknown answer {:?}", line_nr + 1, token);
+            match mode {
+                Mode::Chars if line.is_ascii() => {
+                    for c in line.bytes() {
+                        let question = alphabet.ascii[c as usize]
+                            .ok_or_else(|| unknown(&(c as char).to_string()))?;
+                        mask |= 1 << question;
+                    }
+                }
+                Mode::Chars => {
+                    let mut buf = [0; 4];
+                    for c in line.chars() {
+                        let token = c.encode_utf8(&mut buf);
+                        let question = alphabet.get(token).ok_or_else(|| unknown(token))?;
+                        mask |= 1 << question;
+                    }
+                }
+                Mode::Tokens => {
+                    for token in split_tokens(line) {
+                        let question = alphabet.get(token).ok_or_else(|| unknown(token))?;
+                        mask |= 1 << question;
+                    }
+                }
+            }
+            answers.push(mask);
+        }
+        if answers.len() > group_start {
+            groups.push(group_start..answers.len());
+        }
+
+        Ok(Self {
+            alphabet,
+            answers,
+            groups,
+        })
+    }
+
+    fn read(path: &str, alphabet: Alphabet, mode: Mode) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?, alphabet, mode)
+    }
+
+    fn group(&self, index: usize) -> &[Answers] {
+        &self.answers[self.groups[index].clone()]
+    }
+
+    /// How many members of `group` answered each question.
+    fn frequencies(&self, group: usize) -> Vec<usize> {
+        let mut counts = vec![0; self.alphabet.len()];
+        for answers in self.group(group) {
+            for (question, count) in counts.iter_mut().enumerate() {
+                *count += (answers >> question & 1) as usize;
+            }
+        }
+        counts
+    }
+
+    fn query(&self, group: usize, query: Query) -> Answers {
+        let members = self.group(group);
+        let counted = |pred: &dyn Fn(usize) -> bool| {
+            self.frequencies(group)
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| pred(**count))
+                .fold(0, |mask, (question, _)| mask | 1 << question)
+        };
+        match query {
+            Query::Anyone => members.iter().fold(0, |acc, a| acc | a),
+            Query::Everyone => members.iter().fold(!0, |acc, a| acc & a),
+            Query::AtLeast(k) => counted(&|count| count >= k.max(1)),
+            Query::ExactlyOne => counted(&|count| count == 1),
+            Query::SymmetricDifference => members.iter().fold(0, |acc, a| acc ^ a),
+        }
+    }
+
+    fn query_sum(&self, query: Query) -> usize {
+        (0..self.groups.len())
+            .map(|group| self.query(group, query).count_ones() as usize)
+            .sum()
+    }
+}
+
+/// Which questions of a group to count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Query {
+    Anyone,
+    Everyone,
+    AtLeast(usize),
+    ExactlyOne,
+    /// Questions answered by an odd number of members.
+    SymmetricDifference,
+}
+
+impl Query {
+    fn parse(text: &str) -> Result<Self> {
+        let query = match text.split_once('=') {
+            None if text == "anyone" => Query::Anyone,
+            None if text == "everyone" => Query::Everyone,
+            None if text == "exactly-one" => Query::ExactlyOne,
+            None if text == "symmetric-difference" => Query::SymmetricDifference,
+            Some(("at-least", k)) => Query::AtLeast(k.parse()?),
+            _ => bail!(
+                "invalid query {:?}, expected anyone, everyone, at-least=K, exactly-one or symmetric-difference",
+                text
+            ),
+        };
+        Ok(query)
+    }
+}
+
+// Reference implementation on `HashSet<char>`, kept to benchmark `Survey` against.
+
+fn get_groups_union(path: &str) -> Result<Vec<HashSet<char>>> {
+    let file = File::open(path)?;
     let reader = BufReader::new(file);
 
     let result = reader
@@ -17,8 +225,7 @@ fn get_groups_union() -> Result<Vec<HashSet<char>>> {
         .map(|line_group| {
             line_group
                 .iter()
-                .map(|line| line.chars())
-                .flatten()
+                .flat_map(|line| line.chars())
                 .collect::<HashSet<char>>()
         })
         .collect::<Vec<HashSet<char>>>();
@@ -43,13 +250,13 @@ fn get_intersection(strings: &[String]) -> HashSet<char> {
                     .collect::<HashSet<char>>()
             })
         })
-        .unwrap_or(HashSet::new());
+        .unwrap_or_default();
 
     intersection
 }
 
-fn get_groups_intersection() -> Result<Vec<HashSet<char>>> {
-    let file = File::open("input.txt")?;
+fn get_groups_intersection(path: &str) -> Result<Vec<HashSet<char>>> {
+    let file = File::open(path)?;
     let reader = BufReader::new(file);
 
     let result = reader
@@ -57,22 +264,104 @@ fn get_groups_intersection() -> Result<Vec<HashSet<char>>> {
         .map(|line| line.unwrap())
         .collect::<Vec<String>>()
         .split(|line| line.is_empty())
-        .map(|line_group| get_intersection(line_group.into()))
+        .map(get_intersection)
         .collect::<Vec<HashSet<char>>>();
 
     Ok(result)
 }
 
+fn bench(path: &str) -> Result<()> {
+    let start = Instant::now();
+    let union_sum: usize = get_groups_union(path)?.iter().map(|g| g.len()).sum();
+    let isect_sum: usize = get_groups_intersection(path)?.iter().map(|g| g.len()).sum();
+    let hashset_time = start.elapsed();
+    println!(
+        "HashSet: {} / {} in {} us",
+        union_sum,
+        isect_sum,
+        hashset_time.as_micros()
+    );
+
+    let start = Instant::now();
+    let survey = Survey::read(path, Alphabet::letters(), Mode::Chars)?;
+    let (mask_union, mask_isect) = (
+        survey.query_sum(Query::Anyone),
+        survey.query_sum(Query::Everyone),
+    );
+    let bitset_time = start.elapsed();
+    println!(
+        "bitset:  {} / {} in {} us",
+        mask_union,
+        mask_isect,
+        bitset_time.as_micros()
+    );
+
+    if (union_sum, isect_sum) != (mask_union, mask_isect) {
+        bail!("bitset results differ from the HashSet reference");
+    }
+    println!(
+        "speedup: {:.1}x",
+        hashset_time.as_secs_f64() / bitset_time.as_secs_f64()
+    );
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let start = Instant::now();
 
-    let union: Vec<HashSet<char>> = get_groups_union()?;
-    let union_sum: usize = union.iter().map(|group| group.len()).sum();
-    println!("result: {}", union_sum);
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("bench") {
+        bench(args.get(1).map(String::as_str).unwrap_or("input.txt"))?;
+        return Ok(());
+    }
+
+    let mut queries = vec![Query::Anyone, Query::Everyone];
+    let mut show_frequencies = false;
+    let mut alphabet = None;
+    let mut mode = Mode::Chars;
+    let mut input = String::from("input.txt");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--query" => match args.next() {
+                Some(query) => queries = vec![Query::parse(query)?],
+                None => bail!("missing value for --query"),
+            },
+            "--frequencies" => show_frequencies = true,
+            "--alphabet" => match args.next() {
+                Some(path) => alphabet = Some(Alphabet::parse(&fs::read_to_string(path)?)?),
+                None => bail!("missing value for --alphabet"),
+            },
+            "--tokens" => mode = Mode::Tokens,
+            "--input" => match args.next() {
+                Some(path) => input = path.clone(),
+                None => bail!("missing value for --input"),
+            },
+            _ => bail!("unknown option {}", arg),
+        }
+    }
 
-    let isect: Vec<HashSet<char>> = get_groups_intersection()?;
-    let isect_sum: usize = isect.iter().map(|group| group.len()).sum();
-    println!("result: {}", isect_sum);
+    let alphabet = match (alphabet, mode) {
+        (Some(alphabet), _) => alphabet,
+        (None, Mode::Chars) => Alphabet::letters(),
+        (None, Mode::Tokens) => bail!("--tokens needs an --alphabet declaration"),
+    };
+    let survey = Survey::read(&input, alphabet, mode)?;
+    if show_frequencies {
+        for group in 0..survey.groups.len() {
+            let table = survey
+                .frequencies(group)
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(question, count)| format!("{}={}", survey.alphabet.tokens[question], count))
+                .collect::<Vec<String>>();
+            println!("group {}: {}", group + 1, table.join(" "));
+        }
+    }
+    for query in queries {
+        println!("result: {}", survey.query_sum(query));
+    }
 
     println!("Finished in {} us", start.elapsed().as_micros());
     Ok(())
@@ -113,4 +402,91 @@ mod tests {
         let s2 = into_vec_string(vec!["xy", "zyx", "aybx"]);
         assert_eq!(get_intersection(&s2), into_set(vec!['y', 'x']));
     }
+
+    #[test]
+    fn survey_masks() {
+        let survey = Survey::parse(
+            "abc\n\na\nb\nc\n\nab\nac\n\n\na\na\na\na\n\nb\n",
+            Alphabet::letters(),
+            Mode::Chars,
+        )
+        .unwrap();
+        assert_eq!(survey.groups.len(), 5);
+        assert_eq!(survey.group(1), &[0b001, 0b010, 0b100]);
+        assert_eq!(survey.query_sum(Query::Anyone), 11);
+        assert_eq!(survey.query_sum(Query::Everyone), 6);
+    }
+
+    #[test]
+    fn survey_matches_hashset() {
+        let survey = Survey::read("input.txt", Alphabet::letters(), Mode::Chars).unwrap();
+        let union: usize = get_groups_union("input.txt")
+            .unwrap()
+            .iter()
+            .map(|g| g.len())
+            .sum();
+        let isect: usize = get_groups_intersection("input.txt")
+            .unwrap()
+            .iter()
+            .map(|g| g.len())
+            .sum();
+        assert_eq!(survey.query_sum(Query::Anyone), union);
+        assert_eq!(survey.query_sum(Query::Everyone), isect);
+    }
+
+    #[test]
+    fn survey_rejects_invalid() {
+        let err = Survey::parse("ab\nAb\n", Alphabet::letters(), Mode::Chars)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "line 2: unknown answer \"A\"");
+    }
+
+    #[test]
+    fn quorum_queries() {
+        let survey = Survey::parse("abc\nabd\nae\n", Alphabet::letters(), Mode::Chars).unwrap();
+        assert_eq!(&survey.frequencies(0)[..5], &[3, 2, 1, 1, 1]);
+        let query = |q| survey.query(0, q);
+        assert_eq!(query(Query::AtLeast(2)), 0b00011);
+        assert_eq!(query(Query::AtLeast(3)), query(Query::Everyone));
+        assert_eq!(query(Query::AtLeast(1)), query(Query::Anyone));
+        assert_eq!(query(Query::ExactlyOne), 0b11100);
+        assert_eq!(query(Query::SymmetricDifference), 0b11101);
+    }
+
+    #[test]
+    fn parse_query() {
+        assert_eq!(Query::parse("at-least=2").unwrap(), Query::AtLeast(2));
+        assert_eq!(Query::parse("exactly-one").unwrap(), Query::ExactlyOne);
+        assert!(Query::parse("at-most=2").is_err());
+    }
+
+    #[test]
+    fn token_mode() {
+        let alphabet = Alphabet::parse("Q1, Q2, Q12\nQ20 ä").unwrap();
+        let text = "Q1,Q12\nQ12 Q20, ä\n\nQ2\n";
+        let survey = Survey::parse(text, alphabet, Mode::Tokens).unwrap();
+        assert_eq!(survey.group(0), &[0b00101, 0b11100]);
+        assert_eq!(survey.query(0, Query::Everyone), 0b00100);
+        assert_eq!(survey.query(0, Query::ExactlyOne), 0b11001);
+        assert_eq!(survey.frequencies(0), vec![1, 0, 2, 1, 1]);
+        assert_eq!(survey.query_sum(Query::Anyone), 5);
+    }
+
+    #[test]
+    fn unknown_token() {
+        let alphabet = Alphabet::parse("Q1 Q2").unwrap();
+        let err = Survey::parse("Q1\n\nQ2,Q3\n", alphabet, Mode::Tokens)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "line 3: unknown answer \"Q3\"");
+        assert!(Alphabet::parse("Q1 Q1").is_err());
+    }
+
+    #[test]
+    fn unicode_chars() {
+        let alphabet = Alphabet::parse("é ö x").unwrap();
+        let survey = Survey::parse("éx\nxö\n", alphabet, Mode::Chars).unwrap();
+        assert_eq!(survey.query(0, Query::Everyone), 0b100);
+    }
 }
@@ -0,0 +1,161 @@
+use crate::{CycleError, Graph};
+use std::fmt::Write;
+
+/// Structure of a rule set, as used for judging how complex the packing rules are.
+pub struct Analysis {
+    /// Every bag, each listed before all bags it contains.
+    pub order: Vec<usize>,
+    /// Nesting depth per bag: 0 for an empty bag, else one more than its deepest content.
+    pub depth: Vec<usize>,
+    /// The longest chain of bags, each directly inside the previous one.
+    pub longest_chain: Vec<usize>,
+    /// Bags that no other bag contains.
+    pub roots: Vec<usize>,
+    /// Bags that contain no other bags.
+    pub leaves: Vec<usize>,
+}
+
+impl Graph {
+    pub fn analyze(&self) -> Result<Analysis, CycleError> {
+        let all: Vec<usize> = (0..self.nodes.len()).collect();
+        let mut order = self.post_order(&all)?;
+
+        let mut depth = vec![0; self.nodes.len()];
+        for &node in &order {
+            depth[node] = self.nodes[node]
+                .out_edges
+                .iter()
+                .map(|out_edge| depth[out_edge.other] + 1)
+                .max()
+                .unwrap_or(0);
+        }
+        order.reverse();
+
+        let mut longest_chain = Vec::new();
+        let mut next =
+            (0..self.nodes.len()).max_by_key(|node| (depth[*node], std::cmp::Reverse(*node)));
+        while let Some(node) = next {
+            longest_chain.push(node);
+            next = self.nodes[node]
+                .out_edges
+                .iter()
+                .map(|out_edge| out_edge.other)
+                .find(|other| depth[*other] + 1 == depth[node]);
+        }
+
+        let roots = all
+            .iter()
+            .copied()
+            .filter(|node| self.nodes[*node].in_edges.is_empty())
+            .collect();
+        let leaves = all
+            .iter()
+            .copied()
+            .filter(|node| self.nodes[*node].out_edges.is_empty())
+            .collect();
+
+        Ok(Analysis {
+            order,
+            depth,
+            longest_chain,
+            roots,
+            leaves,
+        })
+    }
+}
+
+impl Analysis {
+    pub fn report(&self, graph: &Graph) -> String {
+        let color = |node: &usize| &*graph.nodes[*node].color;
+        let mut report = String::new();
+        writeln!(
+            report,
+            "{} bags, {} roots, {} leaves",
+            graph.nodes.len(),
+            self.roots.len(),
+            self.leaves.len()
+        )
+        .unwrap();
+        writeln!(
+            report,
+            "longest chain ({} levels): {}",
+            self.longest_chain.len().saturating_sub(1),
+            self.longest_chain
+                .iter()
+                .map(color)
+                .collect::<Vec<&str>>()
+                .join(" -> ")
+        )
+        .unwrap();
+
+        let mut list = |title: &str, nodes: &[usize]| {
+            writeln!(report, "\n{}:", title).unwrap();
+            for node in nodes {
+                writeln!(report, "  {:>3} {}", self.depth[*node], color(node)).unwrap();
+            }
+        };
+        list("roots", &self.roots);
+        list("leaves", &self.leaves);
+        let mut by_depth = self.order.clone();
+        by_depth.sort_by_key(|node| std::cmp::Reverse(self.depth[*node]));
+        list("nesting depth", &by_depth);
+        list("topological order", &self.order);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::read_rules;
+
+    const RULES: &str = "light red bags contain 1 bright white bag, 2 muted yellow bags.
+dark orange bags contain 3 bright white bags, 4 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag, 2 vibrant plum bags.
+dark olive bags contain 3 faded blue bags, 4 dotted black bags.
+vibrant plum bags contain 5 faded blue bags, 6 dotted black bags.
+faded blue bags contain no other bags.
+dotted black bags contain no other bags.
+";
+
+    #[test]
+    fn levels() {
+        let g = read_rules(RULES.as_bytes()).unwrap();
+        let analysis = g.analyze().unwrap();
+        let colors = |nodes: &[usize]| -> Vec<String> {
+            nodes
+                .iter()
+                .map(|n| g.nodes[*n].color.to_string())
+                .collect()
+        };
+
+        assert_eq!(colors(&analysis.roots), vec!["light red", "dark orange"]);
+        assert_eq!(colors(&analysis.leaves), vec!["faded blue", "dotted black"]);
+        assert_eq!(
+            colors(&analysis.longest_chain),
+            vec![
+                "light red",
+                "bright white",
+                "shiny gold",
+                "dark olive",
+                "faded blue"
+            ]
+        );
+        assert_eq!(analysis.depth[g.get_node("shiny gold").unwrap()], 2);
+        assert_eq!(analysis.depth[g.get_node("faded blue").unwrap()], 0);
+
+        let position = |color| {
+            analysis
+                .order
+                .iter()
+                .position(|n| *n == g.get_node(color).unwrap())
+        };
+        for (idx, node) in g.nodes.iter().enumerate() {
+            for out_edge in &node.out_edges {
+                assert!(position(&*node.color) < position(&g.nodes[out_edge.other].color));
+            }
+            assert!(analysis.order.contains(&idx));
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::count::Count;
+use crate::{CycleError, Graph};
+use std::fmt::Write;
+
+/// Everything needed to pack one bag: the nested tree of contents, and how many
+/// bags of each colour that adds up to.
+pub struct BillOfMaterials {
+    root: usize,
+    /// Total number of bags per colour, outermost colours first.
+    totals: Vec<(usize, Count)>,
+}
+
+enum Step<'a> {
+    Enter {
+        depth: usize,
+        node: usize,
+        quantity: usize,
+        total: &'a Count,
+    },
+    Leave,
+}
+
+impl Graph {
+    pub fn bill_of_materials(&self, root: usize) -> Result<BillOfMaterials, CycleError> {
+        let order = self.post_order(&[root])?;
+        let mut needed: Vec<Option<Count>> = vec![None; self.nodes.len()];
+        needed[root] = Some(Count::one());
+        // Outer bags come first, so a bag's count is final before it is passed on.
+        for &node in order.iter().rev() {
+            let count = needed[node].clone().unwrap();
+            for out_edge in &self.nodes[node].out_edges {
+                let inner = needed[out_edge.other].get_or_insert_with(Count::zero);
+                *inner = inner.add(&count.mul(out_edge.weight as u64));
+            }
+        }
+        let totals = order
+            .iter()
+            .rev()
+            .filter(|node| **node != root)
+            .map(|node| (*node, needed[*node].take().unwrap()))
+            .collect();
+        Ok(BillOfMaterials { root, totals })
+    }
+}
+
+impl BillOfMaterials {
+    /// Walks the nested contents depth first, without recursion so deeply nested
+    /// rules can't overflow the stack.
+    fn walk(&self, graph: &Graph, mut visit: impl FnMut(Step)) {
+        // (bag, next edge to expand, number of this bag in the root)
+        let mut stack = vec![(self.root, 0, Count::one())];
+        while let Some((node, edge, total)) = stack.last_mut() {
+            match graph.nodes[*node].out_edges.get(*edge) {
+                Some(out_edge) => {
+                    *edge += 1;
+                    let total = total.mul(out_edge.weight as u64);
+                    visit(Step::Enter {
+                        depth: stack.len(),
+                        node: out_edge.other,
+                        quantity: out_edge.weight,
+                        total: &total,
+                    });
+                    stack.push((out_edge.other, 0, total));
+                }
+                None => {
+                    stack.pop();
+                    if !stack.is_empty() {
+                        visit(Step::Leave);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The tree as indented lines of `<quantity> <colour> (<total>)`, followed by
+    /// the totals per colour.
+    pub fn to_text(&self, graph: &Graph) -> String {
+        let mut text = format!("{}\n", graph.nodes[self.root].color);
+        self.walk(graph, |step| {
+            if let Step::Enter {
+                depth,
+                node,
+                quantity,
+                total,
+            } = step
+            {
+                writeln!(
+                    text,
+                    "{:indent$}{} {} ({})",
+                    "",
+                    quantity,
+                    graph.nodes[node].color,
+                    total,
+                    indent = depth * 2
+                )
+                .unwrap();
+            }
+        });
+        text.push_str("\ntotals:\n");
+        for (node, count) in &self.totals {
+            writeln!(text, "  {} {}", count, graph.nodes[*node].color).unwrap();
+        }
+        text
+    }
+
+    pub fn to_json(&self, graph: &Graph) -> String {
+        let mut json = format!(
+            "{{\"color\":{},\"contents\":[",
+            json_string(&graph.nodes[self.root].color)
+        );
+        // Whether the innermost open `contents` array is still empty.
+        let mut empty = vec![true];
+        self.walk(graph, |step| match step {
+            Step::Enter {
+                node,
+                quantity,
+                total,
+                ..
+            } => {
+                if !std::mem::replace(empty.last_mut().unwrap(), false) {
+                    json.push(',');
+                }
+                write!(
+                    json,
+                    "{{\"color\":{},\"quantity\":{},\"total\":{},\"contents\":[",
+                    json_string(&graph.nodes[node].color),
+                    quantity,
+                    total
+                )
+                .unwrap();
+                empty.push(true);
+            }
+            Step::Leave => {
+                empty.pop();
+                json.push_str("]}");
+            }
+        });
+        json.push_str("],\"totals\":{");
+        for (i, (node, count)) in self.totals.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(json, "{}:{}", json_string(&graph.nodes[*node].color), count).unwrap();
+        }
+        json.push_str("}}");
+        json
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Graph {
+        let mut g = Graph::new();
+        g.insert_edge("shiny gold", "dark olive", 1);
+        g.insert_edge("shiny gold", "vibrant plum", 2);
+        g.insert_edge("dark olive", "faded blue", 3);
+        g.insert_edge("vibrant plum", "faded blue", 5);
+        g
+    }
+
+    #[test]
+    fn text() {
+        let g = example();
+        let bom = g.bill_of_materials(0).unwrap();
+        assert_eq!(
+            bom.to_text(&g),
+            "shiny gold
+  1 dark olive (1)
+    3 faded blue (3)
+  2 vibrant plum (2)
+    5 faded blue (10)
+
+totals:
+  2 vibrant plum
+  1 dark olive
+  13 faded blue
+"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let g = example();
+        let bom = g
+            .bill_of_materials(g.get_node("dark olive").unwrap())
+            .unwrap();
+        assert_eq!(
+            bom.to_json(&g),
+            r#"{"color":"dark olive","contents":[{"color":"faded blue","quantity":3,"total":3,"contents":[]}],"totals":{"faded blue":3}}"#
+        );
+        assert_eq!(json_string("a \"b\"\n"), r#""a \"b\"\u000a""#);
+    }
+}
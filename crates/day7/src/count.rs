@@ -0,0 +1,142 @@
+use std::fmt;
+
+/// An exact number of bags. Counts are kept in a `u64` with checked arithmetic and
+/// only move to a base 2^32 big integer (least significant limb first) once they
+/// no longer fit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Count {
+    Small(u64),
+    Big(Vec<u32>),
+}
+
+impl Count {
+    pub fn zero() -> Self {
+        Count::Small(0)
+    }
+
+    pub fn one() -> Self {
+        Count::Small(1)
+    }
+
+    pub fn add(&self, other: &Count) -> Count {
+        if let (Count::Small(a), Count::Small(b)) = (self, other) {
+            if let Some(sum) = a.checked_add(*b) {
+                return Count::Small(sum);
+            }
+        }
+        let (a, b) = (self.limbs(), other.limbs());
+        let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+        let mut carry = 0u64;
+        for i in 0..a.len().max(b.len()) {
+            let t = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+            sum.push(t as u32);
+            carry = t >> 32;
+        }
+        if carry > 0 {
+            sum.push(carry as u32);
+        }
+        Count::from_limbs(sum)
+    }
+
+    pub fn mul(&self, factor: u64) -> Count {
+        if let Count::Small(a) = self {
+            if let Some(product) = a.checked_mul(factor) {
+                return Count::Small(product);
+            }
+        }
+        let mut product = Vec::with_capacity(self.limbs().len() + 2);
+        let mut carry = 0u128;
+        for limb in self.limbs() {
+            let t = limb as u128 * factor as u128 + carry;
+            product.push(t as u32);
+            carry = t >> 32;
+        }
+        while carry > 0 {
+            product.push(carry as u32);
+            carry >>= 32;
+        }
+        Count::from_limbs(product)
+    }
+
+    fn limbs(&self) -> Vec<u32> {
+        match self {
+            Count::Small(n) => vec![*n as u32, (*n >> 32) as u32],
+            Count::Big(limbs) => limbs.clone(),
+        }
+    }
+
+    fn from_limbs(mut limbs: Vec<u32>) -> Count {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        match limbs.len() {
+            0..=2 => Count::Small(
+                limbs
+                    .iter()
+                    .rev()
+                    .fold(0, |n, limb| (n << 32) | *limb as u64),
+            ),
+            _ => Count::Big(limbs),
+        }
+    }
+}
+
+impl From<u64> for Count {
+    fn from(n: u64) -> Self {
+        Count::Small(n)
+    }
+}
+
+impl fmt::Display for Count {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut limbs = match self {
+            Count::Small(n) => return write!(f, "{}", n),
+            Count::Big(limbs) => limbs.clone(),
+        };
+        // Peel off nine decimal digits at a time, least significant first.
+        let mut chunks = Vec::new();
+        while !limbs.is_empty() {
+            let mut remainder = 0u64;
+            for limb in limbs.iter_mut().rev() {
+                let t = (remainder << 32) | *limb as u64;
+                *limb = (t / 1_000_000_000) as u32;
+                remainder = t % 1_000_000_000;
+            }
+            chunks.push(remainder);
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflow() {
+        let max = Count::from(u64::MAX);
+        assert_eq!(max.add(&Count::one()).to_string(), "18446744073709551616");
+        assert_eq!(
+            max.add(&Count::one()).mul(1 << 32).mul(1 << 32).to_string(),
+            "340282366920938463463374607431768211456"
+        );
+        assert_eq!(max.mul(3).to_string(), "55340232221128654845");
+        assert_eq!(Count::from(7).mul(6), Count::Small(42));
+    }
+
+    #[test]
+    fn normalized() {
+        let big = Count::from(u64::MAX).add(&Count::one());
+        assert!(matches!(big, Count::Big(_)));
+        assert_eq!(Count::from_limbs(vec![5, 0, 0]), Count::Small(5));
+        assert_eq!(Count::zero().add(&Count::zero()), Count::zero());
+    }
+}
@@ -0,0 +1,104 @@
+use crate::Graph;
+use std::fmt::Write;
+
+/// Which part of the graph to export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    All,
+    /// The bag and every bag that (transitively) contains it.
+    Ancestors(usize),
+    /// The bag and every bag it (transitively) contains.
+    Descendants(usize),
+}
+
+fn quote(color: &str) -> String {
+    format!("\"{}\"", color.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Graph {
+    /// Renders the bags in `scope` in Graphviz DOT format, with the quantities as
+    /// edge labels and the queried bag highlighted.
+    pub fn to_dot(&self, scope: Scope) -> String {
+        let (included, highlight) = match scope {
+            Scope::All => (vec![true; self.nodes.len()], None),
+            Scope::Ancestors(node) => (self.mask(node, self.ancestors(node)), Some(node)),
+            Scope::Descendants(node) => (self.mask(node, self.descendants(node)), Some(node)),
+        };
+
+        let mut dot = String::from("digraph bags {\n");
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if !included[idx] {
+                continue;
+            }
+            match highlight == Some(idx) {
+                true => writeln!(
+                    dot,
+                    "    {} [style=filled, fillcolor=gold];",
+                    quote(&node.color)
+                ),
+                false => writeln!(dot, "    {};", quote(&node.color)),
+            }
+            .unwrap();
+        }
+        for (idx, node) in self.nodes.iter().enumerate() {
+            for out_edge in node.out_edges.iter().filter(|_| included[idx]) {
+                if !included[out_edge.other] {
+                    continue;
+                }
+                writeln!(
+                    dot,
+                    "    {} -> {} [label=\"{}\"];",
+                    quote(&node.color),
+                    quote(&self.nodes[out_edge.other].color),
+                    out_edge.weight
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn mask(&self, node: usize, related: Vec<usize>) -> Vec<bool> {
+        let mut mask = vec![false; self.nodes.len()];
+        mask[node] = true;
+        for other in related {
+            mask[other] = true;
+        }
+        mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Graph {
+        let mut g = Graph::new();
+        g.insert_edge("light red", "bright white", 1);
+        g.insert_edge("light red", "muted yellow", 2);
+        g.insert_edge("bright white", "shiny gold", 1);
+        g.insert_edge("shiny gold", "dark \"olive\"", 3);
+        g
+    }
+
+    #[test]
+    fn whole_graph() {
+        let dot = example().to_dot(Scope::All);
+        assert!(dot.starts_with("digraph bags {\n    \"light red\";\n"));
+        assert!(dot.contains("    \"light red\" -> \"muted yellow\" [label=\"2\"];\n"));
+        assert!(dot.contains("\"dark \\\"olive\\\"\""));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn ancestors() {
+        let g = example();
+        let shiny_gold = g.get_node("shiny gold").unwrap();
+        let dot = g.to_dot(Scope::Ancestors(shiny_gold));
+        assert!(dot.contains("    \"shiny gold\" [style=filled, fillcolor=gold];\n"));
+        assert!(dot.contains("\"bright white\" -> \"shiny gold\""));
+        assert!(!dot.contains("muted yellow"));
+        assert!(!dot.contains("olive"));
+    }
+}
@@ -1,21 +1,35 @@
-use anyhow::Result;
-use std::collections::{HashMap, HashSet};
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Write as _};
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::rc::Rc;
 use std::time::Instant;
 
+mod analysis;
+mod bom;
+mod count;
+mod dot;
+mod parser;
+mod whatif;
+
+use count::Count;
+use dot::Scope;
+use parser::parse_rule;
+
 #[derive(Debug)]
 struct Node {
-    color: String,
+    color: Rc<str>,
     in_edges: Vec<Edge>,
     out_edges: Vec<Edge>,
 }
 
 impl Node {
-    fn new(color: &str) -> Self {
+    fn new(color: Rc<str>) -> Self {
         Self {
-            color: String::from(color),
+            color,
             in_edges: Vec::new(),
             out_edges: Vec::new(),
         }
@@ -34,68 +48,306 @@ impl Edge {
     }
 }
 
+/// A bag that transitively contains itself. The path starts and ends with the same colour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CycleError {
+    path: Vec<String>,
+}
+
+impl fmt::Display for CycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cycle in bag rules: {}", self.path.join(" -> "))
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+/// A chain of bags, each directly inside the previous one. `quantities[i]` is how
+/// many of `bags[i + 1]` go into one `bags[i]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ContainmentPath {
+    bags: Vec<usize>,
+    quantities: Vec<usize>,
+    total: Count,
+}
+
+/// Bags are stored in insertion order and addressed by their index in `nodes`.
+/// Colour names are interned: the node and the lookup table share one string.
 #[derive(Debug)]
 struct Graph {
-    node_counter: usize,
-    nodes: HashMap<usize, Node>,
+    nodes: Vec<Node>,
+    index: HashMap<Rc<str>, usize>,
+}
+
+/// Two graphs are equal when they hold the same rules, whatever order the bags
+/// were inserted in.
+impl PartialEq for Graph {
+    fn eq(&self, other: &Self) -> bool {
+        let contents = |graph: &Graph, node: &Node| {
+            node.out_edges
+                .iter()
+                .map(|out_edge| {
+                    (
+                        out_edge.weight,
+                        Rc::clone(&graph.nodes[out_edge.other].color),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        self.nodes.len() == other.nodes.len()
+            && self
+                .nodes
+                .iter()
+                .all(|node| match other.get_node(&node.color) {
+                    Some(idx) => contents(self, node) == contents(other, &other.nodes[idx]),
+                    None => false,
+                })
+    }
 }
 
 impl Graph {
     fn new() -> Self {
         Graph {
-            node_counter: 0,
-            nodes: HashMap::new(),
+            nodes: Vec::new(),
+            index: HashMap::new(),
         }
     }
 
     fn insert_node(&mut self, color: &str) -> usize {
-        self.node_counter += 1;
-        self.nodes.insert(self.node_counter, Node::new(color));
-        self.node_counter
+        let color: Rc<str> = Rc::from(color);
+        self.nodes.push(Node::new(Rc::clone(&color)));
+        self.index.insert(color, self.nodes.len() - 1);
+        self.nodes.len() - 1
     }
 
     fn get_node(&self, color: &str) -> Option<usize> {
-        self.nodes
-            .iter()
-            .find(|(_, node)| node.color == color)
-            .map(|(idx, _)| *idx)
+        self.index.get(color).copied()
     }
 
     fn num_parents(&self, node: usize) -> usize {
-        let mut unique_nodes = HashSet::new();
-        let mut count = 0;
-        for in_edge in &self.nodes.get(&node).unwrap().in_edges {
-            count += self.num_parents_internal(in_edge.other, &mut unique_nodes);
+        self.ancestors(node).len()
+    }
+
+    /// All bags that contain `node`, directly or indirectly.
+    fn ancestors(&self, node: usize) -> Vec<usize> {
+        self.reachable(node, |node| &node.in_edges)
+    }
+
+    /// All bags inside `node`, directly or indirectly.
+    fn descendants(&self, node: usize) -> Vec<usize> {
+        self.reachable(node, |node| &node.out_edges)
+    }
+
+    fn reachable(&self, start: usize, edges: impl Fn(&Node) -> &Vec<Edge>) -> Vec<usize> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut found = Vec::new();
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            for edge in edges(&self.nodes[node]) {
+                if !seen[edge.other] {
+                    seen[edge.other] = true;
+                    found.push(edge.other);
+                    stack.push(edge.other);
+                }
+            }
         }
-        count
+        found
     }
 
-    fn num_parents_internal(&self, node: usize, unique_nodes: &mut HashSet<usize>) -> usize {
-        match unique_nodes.get(&node) {
-            Some(_) => return 0,
-            None => unique_nodes.insert(node),
-        };
-        let mut count = 1;
-        for in_edge in &self.nodes.get(&node).unwrap().in_edges {
-            count += self.num_parents_internal(in_edge.other, unique_nodes);
+    /// Bags that contain `node` directly, each listed once even if several
+    /// rules or edges put `node` into it.
+    fn containers(&self, node: usize) -> Vec<usize> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut containers = Vec::new();
+        for in_edge in &self.nodes[node].in_edges {
+            if !seen[in_edge.other] {
+                seen[in_edge.other] = true;
+                containers.push(in_edge.other);
+            }
         }
-        count
+        containers
     }
 
-    fn num_children(&self, node: usize) -> usize {
-        let mut count = 0;
-        for out_edge in &self.nodes.get(&node).unwrap().out_edges {
-            count += out_edge.weight * self.num_children_internal(out_edge.other);
+    /// Every chain of bags from `outer` down to `inner`, with the number of
+    /// `inner` bags each chain accounts for.
+    fn paths(&self, outer: usize, inner: usize) -> Vec<ContainmentPath> {
+        let mut leads_to_inner = vec![false; self.nodes.len()];
+        leads_to_inner[inner] = true;
+        for node in self.ancestors(inner) {
+            leads_to_inner[node] = true;
+        }
+
+        let mut paths = Vec::new();
+        if !leads_to_inner[outer] || outer == inner {
+            return paths;
+        }
+        // Each stack entry is a bag on the current chain and the next edge to try.
+        let mut stack = vec![(outer, 0)];
+        while let Some(&(node, edge)) = stack.last() {
+            let out_edge = match self.nodes[node].out_edges.get(edge) {
+                Some(out_edge) => *out_edge,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            stack.last_mut().unwrap().1 += 1;
+            if out_edge.other == inner {
+                let mut bags: Vec<usize> = stack.iter().map(|(node, _)| *node).collect();
+                bags.push(inner);
+                let quantities: Vec<usize> = stack
+                    .iter()
+                    .map(|(node, edge)| self.nodes[*node].out_edges[edge - 1].weight)
+                    .collect();
+                let total = quantities
+                    .iter()
+                    .fold(Count::one(), |total, weight| total.mul(*weight as u64));
+                paths.push(ContainmentPath {
+                    bags,
+                    quantities,
+                    total,
+                });
+            } else if leads_to_inner[out_edge.other]
+                && !stack.iter().any(|(node, _)| *node == out_edge.other)
+            {
+                stack.push((out_edge.other, 0));
+            }
         }
-        count
+        paths
     }
 
-    fn num_children_internal(&self, node: usize) -> usize {
-        let mut count = 1;
-        for out_edge in &self.nodes.get(&node).unwrap().out_edges {
-            count += out_edge.weight * self.num_children_internal(out_edge.other);
+    /// Number of bags inside `node`, however deep and however large.
+    fn num_children(&self, node: usize) -> Result<Count, CycleError> {
+        let mut totals = vec![None; self.nodes.len()];
+        self.count_totals(node, &mut totals)?;
+        Ok(self.weighted_sum(node, &totals, Count::zero()))
+    }
+
+    /// Fills `totals` with the number of bags in every bag reachable from `root`,
+    /// the bag itself included. Entries that are already filled in are reused, so
+    /// shared sub-bags are only counted once.
+    fn count_totals(&self, root: usize, totals: &mut [Option<Count>]) -> Result<(), CycleError> {
+        let mut on_stack = vec![false; self.nodes.len()];
+        let mut stack = vec![(root, 0)];
+        on_stack[root] = true;
+        while let Some(&(node, edge)) = stack.last() {
+            match self.nodes[node].out_edges.get(edge) {
+                Some(out_edge) => {
+                    stack.last_mut().unwrap().1 += 1;
+                    let child = out_edge.other;
+                    if totals[child].is_some() {
+                        continue;
+                    }
+                    if on_stack[child] {
+                        return Err(self.cycle_error(&stack, child));
+                    }
+                    on_stack[child] = true;
+                    stack.push((child, 0));
+                }
+                None => {
+                    stack.pop();
+                    on_stack[node] = false;
+                    totals[node] = Some(self.weighted_sum(node, totals, Count::one()));
+                }
+            }
         }
-        count
+        Ok(())
+    }
+
+    /// Builds the error for a DFS `stack` whose top bag contains `repeated`.
+    fn cycle_error(&self, stack: &[(usize, usize)], repeated: usize) -> CycleError {
+        let start = stack
+            .iter()
+            .position(|(node, _)| *node == repeated)
+            .unwrap();
+        let path = stack[start..]
+            .iter()
+            .map(|(node, _)| *node)
+            .chain(Some(repeated))
+            .map(|node| self.nodes[node].color.to_string())
+            .collect();
+        CycleError { path }
+    }
+
+    /// Checks that no bag transitively contains itself.
+    fn validate(&self) -> Result<(), CycleError> {
+        let all: Vec<usize> = (0..self.nodes.len()).collect();
+        self.post_order(&all).map(|_| ())
+    }
+
+    /// Every bag reachable from `roots`, each listed after all bags it contains.
+    fn post_order(&self, roots: &[usize]) -> Result<Vec<usize>, CycleError> {
+        let mut order = Vec::new();
+        let mut done = vec![false; self.nodes.len()];
+        let mut on_stack = vec![false; self.nodes.len()];
+        for &root in roots {
+            if done[root] {
+                continue;
+            }
+            let mut stack = vec![(root, 0)];
+            on_stack[root] = true;
+            while let Some(&(node, edge)) = stack.last() {
+                match self.nodes[node].out_edges.get(edge) {
+                    Some(out_edge) => {
+                        stack.last_mut().unwrap().1 += 1;
+                        let child = out_edge.other;
+                        if on_stack[child] {
+                            return Err(self.cycle_error(&stack, child));
+                        }
+                        if !done[child] {
+                            on_stack[child] = true;
+                            stack.push((child, 0));
+                        }
+                    }
+                    None => {
+                        stack.pop();
+                        on_stack[node] = false;
+                        done[node] = true;
+                        order.push(node);
+                    }
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Writes the graph back as one rule sentence per bag, in the format `read_rules` reads.
+    fn to_rules(&self) -> String {
+        let mut rules = String::new();
+        for node in &self.nodes {
+            let contents = node
+                .out_edges
+                .iter()
+                .map(|out_edge| {
+                    let color = &self.nodes[out_edge.other].color;
+                    match out_edge.weight {
+                        1 => format!("1 {} bag", color),
+                        n => format!("{} {} bags", n, color),
+                    }
+                })
+                .collect::<Vec<String>>();
+            match contents.is_empty() {
+                true => writeln!(rules, "{} bags contain no other bags.", node.color),
+                false => writeln!(
+                    rules,
+                    "{} bags contain {}.",
+                    node.color,
+                    contents.join(", ")
+                ),
+            }
+            .unwrap();
+        }
+        rules
+    }
+
+    fn weighted_sum(&self, node: usize, totals: &[Option<Count>], init: Count) -> Count {
+        self.nodes[node]
+            .out_edges
+            .iter()
+            .fold(init, |count, out_edge| {
+                let total = totals[out_edge.other].as_ref().unwrap();
+                count.add(&total.mul(out_edge.weight as u64))
+            })
     }
 
     fn get_node_or_insert(&mut self, color: &str) -> usize {
@@ -105,82 +357,271 @@ impl Graph {
         }
     }
 
+    /// Removes the bags added after the first `len`, which must not be in any
+    /// rule any more.
+    fn truncate(&mut self, len: usize) {
+        for node in self.nodes.drain(len..) {
+            debug_assert!(node.in_edges.is_empty() && node.out_edges.is_empty());
+            self.index.remove(&node.color);
+        }
+    }
+
+    /// Replaces everything inside `node` with `contents` (quantity, bag) and
+    /// returns what was there before.
+    fn replace_contents(
+        &mut self,
+        node: usize,
+        contents: &[(usize, usize)],
+    ) -> Vec<(usize, usize)> {
+        let old = std::mem::take(&mut self.nodes[node].out_edges);
+        for out_edge in &old {
+            self.nodes[out_edge.other]
+                .in_edges
+                .retain(|in_edge| in_edge.other != node);
+        }
+        for &(weight, other) in contents {
+            self.nodes[node].out_edges.push(Edge::new(weight, other));
+            self.nodes[other].in_edges.push(Edge::new(weight, node));
+        }
+        old.iter()
+            .map(|out_edge| (out_edge.weight, out_edge.other))
+            .collect()
+    }
+
     fn insert_edge(&mut self, from: &str, to: &str, weight: usize) {
         let from_node = self.get_node_or_insert(from);
         let to_node = self.get_node_or_insert(to);
-        self.nodes
-            .get_mut(&from_node)
-            .unwrap()
+        self.nodes[from_node]
             .out_edges
             .push(Edge::new(weight, to_node));
-        self.nodes
-            .get_mut(&to_node)
-            .unwrap()
+        self.nodes[to_node]
             .in_edges
             .push(Edge::new(weight, from_node));
     }
 }
 
-fn read_input() -> Result<Graph> {
-    let file = File::open("input.txt")?;
-    let reader = BufReader::new(file);
+fn read_input(path: &str) -> Result<Graph> {
+    let file = File::open(path)?;
+    read_rules(BufReader::new(file))
+}
 
+fn read_rules(reader: impl BufRead) -> Result<Graph> {
     let mut graph = Graph::new();
 
-    for line in reader.lines() {
-        let parts = line?
-            .split(" bags contain ")
-            .map(|part| part.to_owned())
-            .collect::<Vec<String>>();
-        let outer_color = &parts[0];
-        if parts[1].contains("no other") {
+    for (line_nr, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
             continue;
         }
-        let inner_colors = parts[1]
-            .strip_suffix(".")
-            .unwrap()
-            .split(", ")
-            .map(|inner| {
-                if inner.ends_with(" bags") {
-                    inner.strip_suffix(" bags").unwrap()
-                } else {
-                    inner
-                }
-            })
-            .map(|inner| {
-                if inner.ends_with(" bag") {
-                    inner.strip_suffix(" bag").unwrap()
-                } else {
-                    inner
+        let rule = parse_rule(&line, line_nr + 1)?;
+        graph.get_node_or_insert(&rule.outer);
+        for (weight, color) in rule.contents {
+            graph.insert_edge(&rule.outer, &color, weight);
+        }
+    }
+
+    graph.validate()?;
+    Ok(graph)
+}
+
+/// Tiny xorshift generator, good enough for synthetic rule sets.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// Generates `count` rules over distinct colours. Every bag only contains bags
+/// with a higher index, so the rules never form a cycle.
+fn synthetic_rules(count: usize, seed: u64) -> String {
+    let mut rng = Rng::new(seed);
+    let color = |i: usize| format!("shade{} hue{}", i / 1000, i % 1000);
+
+    let mut rules = String::new();
+    for i in 0..count {
+        let remaining = count - i - 1;
+        let inner = (0..rng.next(5).min(remaining))
+            .map(|_| {
+                let weight = rng.next(5) + 1;
+                let other = i + 1 + rng.next(remaining.min(50));
+                match weight {
+                    1 => format!("1 {} bag", color(other)),
+                    _ => format!("{} {} bags", weight, color(other)),
                 }
             })
-            .map(|inner| {
-                let inner_parts = inner
-                    .splitn(2, " ")
-                    .map(|s| s.to_owned())
-                    .collect::<Vec<String>>();
-                (
-                    inner_parts[0].parse::<usize>().unwrap(),
-                    String::from(&inner_parts[1]),
-                )
-            })
-            .collect::<Vec<(usize, String)>>();
-        for (weight, color) in inner_colors {
-            graph.insert_edge(outer_color, &color, weight);
+            .collect::<Vec<String>>();
+        match inner.is_empty() {
+            true => rules += &format!("{} bags contain no other bags.\n", color(i)),
+            false => rules += &format!("{} bags contain {}.\n", color(i), inner.join(", ")),
         }
     }
+    rules
+}
 
-    Ok(graph)
+fn bench(count: usize) -> Result<()> {
+    let rules = synthetic_rules(count, 2020);
+
+    let start = Instant::now();
+    let graph = read_rules(rules.as_bytes())?;
+    println!(
+        "built graph with {} nodes from {} rules in {} us",
+        graph.nodes.len(),
+        count,
+        start.elapsed().as_micros()
+    );
+
+    let start = Instant::now();
+    for (idx, node) in graph.nodes.iter().enumerate() {
+        if graph.get_node(&node.color) != Some(idx) {
+            bail!("colour {:?} doesn't look up node {}", node.color, idx);
+        }
+    }
+    println!(
+        "looked up {} colours in {} us",
+        graph.nodes.len(),
+        start.elapsed().as_micros()
+    );
+    Ok(())
+}
+
+/// Removes `name` and the value following it from `args`.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(value))
+        }
+        Some(_) => bail!("missing value for {}", name),
+        None => Ok(None),
+    }
+}
+
+fn node_for(graph: &Graph, color: &str) -> Result<usize> {
+    graph
+        .get_node(color)
+        .ok_or_else(|| anyhow!("unknown colour {:?}", color))
+}
+
+fn print_colors(graph: &Graph, nodes: Vec<usize>) {
+    let mut colors: Vec<&str> = nodes
+        .iter()
+        .map(|node| &*graph.nodes[*node].color)
+        .collect();
+    colors.sort_unstable();
+    for color in &colors {
+        println!("{}", color);
+    }
+    println!("{} bags", colors.len());
 }
 
 fn main() -> Result<()> {
     let start = Instant::now();
 
-    let graph = read_input()?;
-    let shiny_gold = graph.get_node("shiny gold").unwrap();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let input = take_option(&mut args, "--input")?.unwrap_or_else(|| "input.txt".into());
+    let mut watched = Vec::new();
+    while let Some(color) = take_option(&mut args, "--watch")? {
+        watched.push(color);
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args[..] {
+        ["bench"] => return bench(100_000),
+        ["bench", count] => return bench(count.parse()?),
+        _ => (),
+    }
+
+    let mut graph = read_input(&input)?;
+    match args[..] {
+        ["whatif"] => {
+            if watched.is_empty() {
+                bail!("usage: whatif --watch COLOR [--watch COLOR ...] < edits");
+            }
+            let watched = watched
+                .iter()
+                .map(|color| node_for(&graph, color))
+                .collect::<Result<Vec<usize>>>()?;
+            let stdin = std::io::stdin();
+            return whatif::run(&mut graph, watched, stdin.lock());
+        }
+        ["dot", ref options @ ..] => {
+            let scope = match options {
+                [] => Scope::All,
+                ["--ancestors", color] => Scope::Ancestors(node_for(&graph, color)?),
+                ["--descendants", color] => Scope::Descendants(node_for(&graph, color)?),
+                _ => bail!("usage: dot [--ancestors COLOR | --descendants COLOR]"),
+            };
+            print!("{}", graph.to_dot(scope));
+            return Ok(());
+        }
+        ["report"] => {
+            print!("{}", graph.analyze()?.report(&graph));
+            return Ok(());
+        }
+        ["format"] => {
+            print!("{}", graph.to_rules());
+            return Ok(());
+        }
+        ["bom", color] => {
+            print!(
+                "{}",
+                graph
+                    .bill_of_materials(node_for(&graph, color)?)?
+                    .to_text(&graph)
+            );
+            return Ok(());
+        }
+        ["bom", color, "--json"] => {
+            println!(
+                "{}",
+                graph
+                    .bill_of_materials(node_for(&graph, color)?)?
+                    .to_json(&graph)
+            );
+            return Ok(());
+        }
+        ["ancestors", color] => {
+            print_colors(&graph, graph.ancestors(node_for(&graph, color)?));
+            return Ok(());
+        }
+        ["descendants", color] => {
+            print_colors(&graph, graph.descendants(node_for(&graph, color)?));
+            return Ok(());
+        }
+        ["containers", color] => {
+            print_colors(&graph, graph.containers(node_for(&graph, color)?));
+            return Ok(());
+        }
+        ["paths", outer, inner] => {
+            let paths = graph.paths(node_for(&graph, outer)?, node_for(&graph, inner)?);
+            for path in &paths {
+                let mut line = graph.nodes[path.bags[0]].color.to_string();
+                for (bag, quantity) in path.bags[1..].iter().zip(&path.quantities) {
+                    line += &format!(" -[{}]-> {}", quantity, graph.nodes[*bag].color);
+                }
+                println!("{}: {}", line, path.total);
+            }
+            println!("{} paths", paths.len());
+            return Ok(());
+        }
+        [] => (),
+        _ => bail!("unknown command {:?}", args.join(" ")),
+    }
+
+    let shiny_gold = node_for(&graph, "shiny gold")?;
 
     println!("result part 1: {}", graph.num_parents(shiny_gold));
-    println!("result part 2: {}", graph.num_children(shiny_gold));
+    println!("result part 2: {}", graph.num_children(shiny_gold)?);
 
     println!("Finished in {} us", start.elapsed().as_micros());
     Ok(())
@@ -192,7 +633,240 @@ mod tests {
 
     #[test]
     fn graph() {
-        let g = read_input().unwrap();
+        let g = read_input("input.txt").unwrap();
         println!("{:#?}", g);
     }
+
+    #[test]
+    fn lookup() {
+        let g = read_rules(synthetic_rules(2000, 7).as_bytes()).unwrap();
+        for (idx, node) in g.nodes.iter().enumerate() {
+            assert_eq!(g.get_node(&node.color), Some(idx));
+            assert!(Rc::ptr_eq(
+                &node.color,
+                g.index.get_key_value(&*node.color).unwrap().0
+            ));
+        }
+        assert_eq!(g.get_node("shiny gold"), None);
+    }
+
+    fn chain(depth: usize, weight: usize) -> Graph {
+        let rules = (0..depth)
+            .map(|i| format!("c{} x bags contain {} c{} x bags.\n", i, weight, i + 1))
+            .collect::<String>();
+        read_rules(rules.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let g = chain(100_000, 1);
+        assert_eq!(g.num_children(0).unwrap(), Count::from(100_000));
+    }
+
+    #[test]
+    fn huge_counts() {
+        // 2 + 4 + ... + 2^100 = 2^101 - 2
+        let g = chain(100, 2);
+        assert_eq!(
+            g.num_children(0).unwrap().to_string(),
+            "2535301200456458802993406410750"
+        );
+    }
+
+    #[test]
+    fn shared_sub_bags() {
+        // Every layer holds two of each bag in the next layer, which is exponential
+        // without memoization.
+        let rules = (0..60)
+            .map(|i| {
+                format!(
+                    "a{i} x bags contain 2 a{j} x bags, 2 b{j} x bags.\nb{i} x bags contain 2 a{j} x bags, 2 b{j} x bags.\n",
+                    i = i,
+                    j = i + 1
+                )
+            })
+            .collect::<String>();
+        let g = read_rules(rules.as_bytes()).unwrap();
+        let expected = (1..=60).fold(Count::zero(), |sum, _| sum.mul(4).add(&Count::from(4)));
+        assert_eq!(g.num_children(0).unwrap(), expected);
+    }
+
+    #[test]
+    fn cycle() {
+        let rules = "light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 bright white bags.
+shiny gold bags contain 3 dark olive bags.
+dark olive bags contain 1 muted yellow bag.
+";
+        let err = read_rules(rules.as_bytes()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "cycle in bag rules: bright white -> shiny gold -> dark olive -> muted yellow -> bright white"
+        );
+
+        let mut g = Graph::new();
+        g.insert_edge("a", "b", 1);
+        g.insert_edge("b", "a", 1);
+        g.insert_edge("c", "a", 1);
+        let err = g.num_children(2).unwrap_err();
+        assert_eq!(err.path.join(" -> "), "a -> b -> a");
+    }
+
+    fn example() -> Graph {
+        let rules = "light red bags contain 1 bright white bag, 2 muted yellow bags.
+dark orange bags contain 3 bright white bags, 4 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag, 2 vibrant plum bags.
+dark olive bags contain 3 faded blue bags, 4 dotted black bags.
+vibrant plum bags contain 5 faded blue bags, 6 dotted black bags.
+faded blue bags contain no other bags.
+dotted black bags contain no other bags.
+";
+        read_rules(rules.as_bytes()).unwrap()
+    }
+
+    fn colors(g: &Graph, nodes: Vec<usize>) -> Vec<&str> {
+        let mut colors: Vec<&str> = nodes.iter().map(|n| &*g.nodes[*n].color).collect();
+        colors.sort_unstable();
+        colors
+    }
+
+    #[test]
+    fn queries() {
+        let g = example();
+        let shiny_gold = g.get_node("shiny gold").unwrap();
+        assert_eq!(
+            colors(&g, g.ancestors(shiny_gold)),
+            vec!["bright white", "dark orange", "light red", "muted yellow"]
+        );
+        assert_eq!(
+            colors(&g, g.descendants(shiny_gold)),
+            vec!["dark olive", "dotted black", "faded blue", "vibrant plum"]
+        );
+        assert_eq!(
+            colors(&g, g.containers(shiny_gold)),
+            vec!["bright white", "muted yellow"]
+        );
+        assert_eq!(g.num_children(shiny_gold).unwrap(), Count::from(32));
+    }
+
+    #[test]
+    fn diamond() {
+        let mut g = Graph::new();
+        g.insert_edge("top", "left", 1);
+        g.insert_edge("top", "right", 2);
+        g.insert_edge("left", "bottom", 3);
+        g.insert_edge("right", "bottom", 4);
+        g.insert_edge("right", "bottom", 5);
+        let bottom = g.get_node("bottom").unwrap();
+        let top = g.get_node("top").unwrap();
+        assert_eq!(colors(&g, g.containers(bottom)), vec!["left", "right"]);
+        assert_eq!(
+            colors(&g, g.ancestors(bottom)),
+            vec!["left", "right", "top"]
+        );
+        assert_eq!(
+            colors(&g, g.descendants(top)),
+            vec!["bottom", "left", "right"]
+        );
+        assert_eq!(g.paths(top, bottom).len(), 3);
+    }
+
+    #[test]
+    fn containment_paths() {
+        let g = example();
+        let light_red = g.get_node("light red").unwrap();
+        let faded_blue = g.get_node("faded blue").unwrap();
+        let paths = g.paths(light_red, faded_blue);
+        let totals: Vec<String> = paths.iter().map(|p| p.total.to_string()).collect();
+        // via bright white, then via muted yellow (directly and through shiny gold)
+        assert_eq!(totals, vec!["3", "10", "12", "40", "18"]);
+        assert_eq!(
+            colors(&g, paths[4].bags.clone()),
+            vec!["faded blue", "light red", "muted yellow"]
+        );
+        assert_eq!(paths[4].quantities, vec![2, 9]);
+        assert!(g.paths(faded_blue, light_red).is_empty());
+    }
+
+    /// A random acyclic graph: bags only contain bags with a higher index, and
+    /// some bags are left empty.
+    fn random_graph(seed: u64) -> Graph {
+        let mut rng = Rng::new(seed + 1);
+        let mut g = Graph::new();
+        let size = 1 + rng.next(40);
+        for i in 0..size {
+            g.get_node_or_insert(&format!("shade{} hue{}", i, rng.next(3)));
+        }
+        for i in 0..size {
+            for _ in 0..rng.next(4) {
+                let j = i + rng.next(size - i);
+                if j > i {
+                    let (outer, inner) = (g.nodes[i].color.clone(), g.nodes[j].color.clone());
+                    g.insert_edge(&outer, &inner, 1 + rng.next(20));
+                }
+            }
+        }
+        g
+    }
+
+    #[test]
+    fn round_trip() {
+        for seed in 0..200 {
+            let g = random_graph(seed);
+            let rules = g.to_rules();
+            let parsed = read_rules(rules.as_bytes()).unwrap();
+            assert_eq!(parsed, g, "seed {}:\n{}", seed, rules);
+            assert_eq!(read_rules(parsed.to_rules().as_bytes()).unwrap(), g);
+        }
+    }
+
+    #[test]
+    fn round_trip_input() {
+        let text = std::fs::read_to_string("input.txt").unwrap();
+        let g = read_rules(text.as_bytes()).unwrap();
+        let rules = g.to_rules();
+        assert_eq!(read_rules(rules.as_bytes()).unwrap(), g);
+
+        let mut original: Vec<&str> = text.lines().collect();
+        let mut written: Vec<&str> = rules.lines().collect();
+        original.sort_unstable();
+        written.sort_unstable();
+        assert_eq!(written, original);
+    }
+
+    #[test]
+    fn plurals() {
+        let mut g = Graph::new();
+        g.insert_edge("light red", "bright white", 1);
+        g.insert_edge("light red", "muted yellow", 2);
+        assert_eq!(
+            g.to_rules(),
+            "light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain no other bags.
+muted yellow bags contain no other bags.
+"
+        );
+        let mut other = Graph::new();
+        other.insert_edge("light red", "bright white", 2);
+        other.insert_edge("light red", "muted yellow", 2);
+        assert_ne!(g, other);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let rules = "light red bags contain 1 bright white bag.\n\nbright white bags contain 1 shiny gold\n";
+        let err = read_rules(rules.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<parser::ParseError>(),
+            Some(&parser::ParseError {
+                line: 3,
+                column: 39,
+                expected: "\"bags\"".into(),
+                found: "end of line".into(),
+            })
+        );
+    }
 }
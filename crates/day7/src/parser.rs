@@ -0,0 +1,271 @@
+//! Parser for rule sentences such as
+//! `light red bags contain 1 bright white bag, 2 muted yellow bags.`
+//!
+//! Grammar, with tokens separated by any amount of whitespace:
+//!
+//! ```text
+//! rule     := color bags "contain" contents ["."]
+//! contents := "no" "other" bags | item ("," item)*
+//! item     := quantity color bags
+//! color    := word+
+//! bags     := "bag" | "bags"
+//! ```
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub expected: String,
+    pub found: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: expected {}, found {}",
+            self.line, self.column, self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A parsed rule: the outer colour and the quantity and colour of each bag inside.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub outer: String,
+    pub contents: Vec<(usize, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Word(&'a str),
+    Comma,
+    Period,
+    End,
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{:?}", word),
+            Token::Comma => write!(f, "\",\""),
+            Token::Period => write!(f, "\".\""),
+            Token::End => write!(f, "end of line"),
+        }
+    }
+}
+
+struct Parser<'a> {
+    /// Tokens with their 1-based column, always ending with `Token::End`.
+    tokens: Vec<(Token<'a>, usize)>,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str, line: usize) -> Self {
+        let mut tokens = Vec::new();
+        let mut word_start = None;
+        let mut columns = 0;
+        for (column, (i, c)) in text.char_indices().enumerate() {
+            let is_word = !(c.is_whitespace() || c == ',' || c == '.');
+            if let (Some((start, start_column)), false) = (word_start, is_word) {
+                tokens.push((Token::Word(&text[start..i]), start_column));
+                word_start = None;
+            }
+            match c {
+                ',' => tokens.push((Token::Comma, column + 1)),
+                '.' => tokens.push((Token::Period, column + 1)),
+                _ if is_word && word_start.is_none() => word_start = Some((i, column + 1)),
+                _ => (),
+            }
+            columns = column + 1;
+        }
+        if let Some((start, start_column)) = word_start {
+            tokens.push((Token::Word(&text[start..]), start_column));
+        }
+        tokens.push((Token::End, columns + 1));
+        Self {
+            tokens,
+            pos: 0,
+            line,
+        }
+    }
+
+    fn peek(&self) -> Token<'a> {
+        self.tokens[self.pos].0
+    }
+
+    fn advance(&mut self) -> Token<'a> {
+        let token = self.peek();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, expected: &str) -> ParseError {
+        let (token, column) = self.tokens[self.pos];
+        ParseError {
+            line: self.line,
+            column,
+            expected: expected.to_owned(),
+            found: token.to_string(),
+        }
+    }
+
+    fn expect_word(&mut self, words: &[&str], expected: &str) -> Result<(), ParseError> {
+        match self.peek() {
+            Token::Word(word) if words.contains(&word) => {
+                self.advance();
+                Ok(())
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    /// One or more words, up to `bag` or `bags`, which are consumed as well.
+    fn color(&mut self) -> Result<String, ParseError> {
+        let mut words = Vec::new();
+        loop {
+            match self.peek() {
+                Token::Word("bag") | Token::Word("bags") if !words.is_empty() => {
+                    self.advance();
+                    return Ok(words.join(" "));
+                }
+                Token::Word(word) => {
+                    words.push(word);
+                    self.advance();
+                }
+                _ if words.is_empty() => return Err(self.error("a colour")),
+                _ => return Err(self.error("\"bags\"")),
+            }
+        }
+    }
+
+    fn item(&mut self) -> Result<(usize, String), ParseError> {
+        let quantity = match self.peek() {
+            Token::Word(word) => word.parse::<usize>().ok().filter(|n| *n > 0),
+            _ => None,
+        };
+        match quantity {
+            Some(quantity) => {
+                self.advance();
+                Ok((quantity, self.color()?))
+            }
+            None => Err(self.error("a quantity")),
+        }
+    }
+
+    fn rule(&mut self) -> Result<Rule, ParseError> {
+        let outer = self.color()?;
+        self.expect_word(&["contain", "contains"], "\"contain\"")?;
+
+        let mut contents = Vec::new();
+        if self.peek() == Token::Word("no") {
+            self.advance();
+            self.expect_word(&["other"], "\"other\"")?;
+            self.expect_word(&["bag", "bags"], "\"bags\"")?;
+        } else {
+            contents.push(self.item()?);
+            while self.peek() == Token::Comma {
+                self.advance();
+                contents.push(self.item()?);
+            }
+        }
+
+        if self.peek() == Token::Period {
+            self.advance();
+        }
+        match self.peek() {
+            Token::End => Ok(Rule { outer, contents }),
+            Token::Word(_) if contents.is_empty() => Err(self.error("\".\" or end of line")),
+            _ => Err(self.error("\",\", \".\" or end of line")),
+        }
+    }
+}
+
+/// Parses one rule sentence. `line` is only used for error messages.
+pub fn parse_rule(text: &str, line: usize) -> Result<Rule, ParseError> {
+    Parser::new(text, line).rule()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(text: &str) -> Rule {
+        parse_rule(text, 1).unwrap()
+    }
+
+    fn error(text: &str) -> String {
+        parse_rule(text, 7).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn well_formed() {
+        assert_eq!(
+            rule("light red bags contain 1 bright white bag, 2 muted yellow bags."),
+            Rule {
+                outer: "light red".into(),
+                contents: vec![(1, "bright white".into()), (2, "muted yellow".into())],
+            }
+        );
+        assert_eq!(
+            rule("faded blue bags contain no other bags."),
+            Rule {
+                outer: "faded blue".into(),
+                contents: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn tolerant() {
+        let expected = rule("light red bags contain 1 bright white bag, 2 muted yellow bags.");
+        assert_eq!(
+            rule("  light   red bags contain 1 bright\twhite bag ,2 muted yellow bags "),
+            expected
+        );
+        assert_eq!(
+            rule("light red bags contains 1 bright white bags, 2 muted yellow bag"),
+            expected
+        );
+        assert_eq!(
+            rule("faded blue bags contain no other bags").contents,
+            vec![]
+        );
+    }
+
+    #[test]
+    fn diagnostics() {
+        assert_eq!(
+            error("light red bags hold 1 bright white bag."),
+            "line 7, column 16: expected \"contain\", found \"hold\""
+        );
+        assert_eq!(
+            error("light red bags contain two bright white bags."),
+            "line 7, column 24: expected a quantity, found \"two\""
+        );
+        assert_eq!(
+            error("light red bags contain 1 bright white, 2 muted yellow bags."),
+            "line 7, column 38: expected \"bags\", found \",\""
+        );
+        assert_eq!(
+            error("light red bags contain 1 bright white bag. 2 muted yellow bags."),
+            "line 7, column 44: expected \",\", \".\" or end of line, found \"2\""
+        );
+        assert_eq!(
+            error("light red bags contain"),
+            "line 7, column 23: expected a quantity, found end of line"
+        );
+        assert_eq!(
+            error("faded blue bags contain no bags."),
+            "line 7, column 28: expected \"other\", found \"bags\""
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use crate::count::Count;
+use crate::parser::parse_rule;
+use crate::Graph;
+use anyhow::{anyhow, bail, Result};
+use std::io::prelude::*;
+
+/// A change to the rules of a loaded graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    /// `add <rule sentence>`: sets the contents of a bag, replacing its old rule.
+    Rule {
+        outer: String,
+        contents: Vec<(usize, String)>,
+    },
+    /// `remove <colour>`: empties a bag.
+    Remove { outer: String },
+    /// `set <outer> -> <quantity> <inner>`: changes one quantity, 0 removes it.
+    Set {
+        outer: String,
+        inner: String,
+        quantity: usize,
+    },
+}
+
+impl Edit {
+    pub fn parse(line: &str) -> Result<Self> {
+        let (command, rest) = line
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("invalid edit {:?}", line))?;
+        let edit = match command {
+            "add" => {
+                let rule = parse_rule(rest, 1)?;
+                Edit::Rule {
+                    outer: rule.outer,
+                    contents: rule.contents,
+                }
+            }
+            "remove" => Edit::Remove {
+                outer: rest.trim().to_owned(),
+            },
+            "set" => {
+                let (outer, inner) = rest
+                    .split_once("->")
+                    .ok_or_else(|| anyhow!("expected `set <outer> -> <quantity> <inner>`"))?;
+                let (quantity, inner) = inner
+                    .trim()
+                    .split_once(' ')
+                    .ok_or_else(|| anyhow!("expected `set <outer> -> <quantity> <inner>`"))?;
+                Edit::Set {
+                    outer: outer.trim().to_owned(),
+                    inner: inner.trim().to_owned(),
+                    quantity: quantity.parse()?,
+                }
+            }
+            _ => bail!("unknown edit {:?}, expected add, remove or set", command),
+        };
+        Ok(edit)
+    }
+}
+
+/// The counts reported for a watched bag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counts {
+    pub ancestors: usize,
+    pub contents: Count,
+}
+
+/// Keeps the counts of some bags up to date while the graph is edited. Bag totals
+/// are cached between edits and only the bags above an edit are recounted.
+pub struct Watcher {
+    watched: Vec<usize>,
+    counts: Vec<Counts>,
+    totals: Vec<Option<Count>>,
+    /// How many bag totals the last update had to recompute.
+    pub recomputed: usize,
+}
+
+impl Watcher {
+    pub fn new(graph: &Graph, watched: Vec<usize>) -> Result<Self> {
+        let mut totals = vec![None; graph.nodes.len()];
+        let mut counts = Vec::new();
+        for &node in &watched {
+            graph.count_totals(node, &mut totals)?;
+            counts.push(Counts {
+                ancestors: graph.num_parents(node),
+                contents: graph.weighted_sum(node, &totals, Count::zero()),
+            });
+        }
+        Ok(Self {
+            watched,
+            counts,
+            totals,
+            recomputed: 0,
+        })
+    }
+
+    pub fn watched(&self) -> impl Iterator<Item = (usize, &Counts)> {
+        self.watched.iter().copied().zip(&self.counts)
+    }
+
+    /// Applies `edit` to `graph` and returns the watched bags whose counts
+    /// changed, with their old and new counts. An edit that would make a bag
+    /// contain itself is rolled back.
+    pub fn apply(
+        &mut self,
+        graph: &mut Graph,
+        edit: &Edit,
+    ) -> Result<Vec<(usize, Counts, Counts)>> {
+        let len = graph.nodes.len();
+        let (outer, contents) = match edit {
+            Edit::Rule { outer, contents } => {
+                let outer = graph.get_node_or_insert(outer);
+                let contents = contents
+                    .iter()
+                    .map(|(weight, color)| (*weight, graph.get_node_or_insert(color)))
+                    .collect();
+                (outer, contents)
+            }
+            Edit::Remove { outer } => (node(graph, outer)?, Vec::new()),
+            Edit::Set {
+                outer,
+                inner,
+                quantity,
+            } => {
+                let outer = node(graph, outer)?;
+                let inner = match quantity {
+                    0 => node(graph, inner)?,
+                    _ => graph.get_node_or_insert(inner),
+                };
+                let mut contents: Vec<(usize, usize)> = graph.nodes[outer]
+                    .out_edges
+                    .iter()
+                    .map(|out_edge| (out_edge.weight, out_edge.other))
+                    .collect();
+                match contents.iter().position(|(_, other)| *other == inner) {
+                    Some(i) if *quantity == 0 => {
+                        contents.remove(i);
+                    }
+                    Some(i) => contents[i].0 = *quantity,
+                    None if *quantity == 0 => (),
+                    None => contents.push((*quantity, inner)),
+                }
+                (outer, contents)
+            }
+        };
+        let old = graph.replace_contents(outer, &contents);
+        // The graph had no cycles before, so a new one has to run through `outer`.
+        if let Err(err) = graph.post_order(&[outer]) {
+            graph.replace_contents(outer, &old);
+            graph.truncate(len);
+            return Err(err.into());
+        }
+        self.totals.resize(graph.nodes.len(), None);
+
+        // Only `outer` and the bags containing it hold a different number of bags now.
+        self.totals[outer] = None;
+        for ancestor in graph.ancestors(outer) {
+            self.totals[ancestor] = None;
+        }
+        // Only bags below an inner bag that was added or removed gain or lose containers.
+        let mut below = vec![false; graph.nodes.len()];
+        for &(_, inner) in old.iter().chain(&contents) {
+            below[inner] = true;
+            for descendant in graph.descendants(inner) {
+                below[descendant] = true;
+            }
+        }
+
+        let missing = self.totals.iter().filter(|total| total.is_none()).count();
+        let mut changes = Vec::new();
+        for (&node, counts) in self.watched.iter().zip(self.counts.iter_mut()) {
+            graph.count_totals(node, &mut self.totals)?;
+            let new = Counts {
+                ancestors: match below[node] {
+                    true => graph.num_parents(node),
+                    false => counts.ancestors,
+                },
+                contents: graph.weighted_sum(node, &self.totals, Count::zero()),
+            };
+            if new != *counts {
+                changes.push((node, counts.clone(), new.clone()));
+                *counts = new;
+            }
+        }
+        self.recomputed = missing - self.totals.iter().filter(|total| total.is_none()).count();
+        Ok(changes)
+    }
+}
+
+fn node(graph: &Graph, color: &str) -> Result<usize> {
+    graph
+        .get_node(color)
+        .ok_or_else(|| anyhow!("unknown colour {:?}", color))
+}
+
+/// Reads edits line by line and reports how the watched counts change.
+pub fn run(graph: &mut Graph, watched: Vec<usize>, input: impl BufRead) -> Result<()> {
+    let mut watcher = Watcher::new(graph, watched)?;
+    for (node, counts) in watcher.watched() {
+        println!(
+            "{}: {} containers, {} bags inside",
+            graph.nodes[node].color, counts.ancestors, counts.contents
+        );
+    }
+
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let edit = match Edit::parse(&line) {
+            Ok(edit) => edit,
+            Err(err) => {
+                println!("error: {}", err);
+                continue;
+            }
+        };
+        match watcher.apply(graph, &edit) {
+            Ok(changes) if changes.is_empty() => println!(
+                "no watched counts changed ({} totals recomputed)",
+                watcher.recomputed
+            ),
+            Ok(changes) => {
+                for (node, old, new) in changes {
+                    println!(
+                        "{}: {} -> {} containers, {} -> {} bags inside",
+                        graph.nodes[node].color,
+                        old.ancestors,
+                        new.ancestors,
+                        old.contents,
+                        new.contents
+                    );
+                }
+                println!("({} totals recomputed)", watcher.recomputed);
+            }
+            Err(err) => println!("error: {}, edit rolled back", err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_rules;
+
+    const RULES: &str = "light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag.
+dark olive bags contain 3 faded blue bags.
+faded blue bags contain no other bags.
+";
+
+    fn edit(line: &str) -> Edit {
+        Edit::parse(line).unwrap()
+    }
+
+    #[test]
+    fn parse_edits() {
+        assert_eq!(
+            edit("set light red -> 5 bright white"),
+            Edit::Set {
+                outer: "light red".into(),
+                inner: "bright white".into(),
+                quantity: 5
+            }
+        );
+        assert_eq!(
+            edit("add pale teal bags contain 2 faded blue bags."),
+            Edit::Rule {
+                outer: "pale teal".into(),
+                contents: vec![(2, "faded blue".into())]
+            }
+        );
+        assert!(Edit::parse("rename a -> b").is_err());
+        assert!(Edit::parse("add pale teal bags contain 2").is_err());
+    }
+
+    #[test]
+    fn incremental_counts() {
+        let mut g = read_rules(RULES.as_bytes()).unwrap();
+        let shiny_gold = g.get_node("shiny gold").unwrap();
+        let light_red = g.get_node("light red").unwrap();
+        let mut watcher = Watcher::new(&g, vec![shiny_gold, light_red]).unwrap();
+        let fresh = |g: &Graph, node| Counts {
+            ancestors: g.num_parents(node),
+            contents: g.num_children(node).unwrap(),
+        };
+
+        let edits = [
+            "set dark olive -> 4 faded blue",
+            "add pale teal bags contain 2 shiny gold bags.",
+            "set muted yellow -> 0 shiny gold",
+            "remove light red",
+            "add light red bags contain 3 dark olive bags.",
+        ];
+        for line in &edits {
+            watcher.apply(&mut g, &edit(line)).unwrap();
+            for (node, counts) in watcher.watched() {
+                assert_eq!(*counts, fresh(&g, node), "after {:?}", line);
+            }
+        }
+        assert_eq!(g, read_rules(g.to_rules().as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn only_ancestors_are_recounted() {
+        let mut g = read_rules(RULES.as_bytes()).unwrap();
+        let light_red = g.get_node("light red").unwrap();
+        let mut watcher = Watcher::new(&g, vec![light_red]).unwrap();
+        let changes = watcher
+            .apply(&mut g, &edit("set muted yellow -> 10 faded blue"))
+            .unwrap();
+        // muted yellow and light red
+        assert_eq!(watcher.recomputed, 2);
+        assert_eq!(changes[0].1.contents, Count::from(46));
+        assert_eq!(changes[0].2.contents, Count::from(48));
+    }
+
+    #[test]
+    fn cycle_is_rolled_back() {
+        let mut g = read_rules(RULES.as_bytes()).unwrap();
+        let light_red = g.get_node("light red").unwrap();
+        let mut watcher = Watcher::new(&g, vec![light_red]).unwrap();
+        let before = g.to_rules();
+        let err = watcher
+            .apply(&mut g, &edit("set dark olive -> 1 light red"))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("cycle in bag rules"));
+        assert_eq!(g.to_rules(), before);
+
+        // Bags that only the rejected rule mentioned are dropped again.
+        let nodes = g.nodes.len();
+        let err = watcher.apply(
+            &mut g,
+            &edit("add dark olive bags contain 1 pale teal bag, 2 light red bags."),
+        );
+        assert!(err.is_err());
+        assert_eq!(g.nodes.len(), nodes);
+        assert_eq!(g.get_node("pale teal"), None);
+        assert_eq!(g.to_rules(), before);
+        watcher
+            .apply(&mut g, &edit("add pale teal bags contain 1 light red bag."))
+            .unwrap();
+        assert_eq!(g.get_node("pale teal"), Some(nodes));
+    }
+}
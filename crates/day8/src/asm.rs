@@ -0,0 +1,193 @@
+//! Textual assembler and disassembler for the handheld.
+//!
+//! Source lines look like `loop_start: acc +1 # comment`. Labels end in `:` and
+//! may stand on their own line, comments start with `#` or `;`, and `jmp`/`nop`
+//! take either a relative offset or a label to jump to.
+
+use crate::isa::Decode;
+use crate::Instruction;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn assemble(source: &str) -> Result<Vec<Instruction>, AsmError> {
+    assemble_with(source)
+}
+
+/// Assembles a program for any instruction set.
+pub fn assemble_with<I: Decode>(source: &str) -> Result<Vec<I>, AsmError> {
+    let mut labels = HashMap::new();
+    // (line number, mnemonic, operands) per instruction
+    let mut lines = Vec::new();
+
+    for (line_nr, line) in source.lines().enumerate() {
+        let line_nr = line_nr + 1;
+        let error = |message: String| AsmError {
+            line: line_nr,
+            message,
+        };
+        let mut code = line.split(['#', ';']).next().unwrap().trim();
+        while let Some((label, rest)) = code.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) {
+                return Err(error(format!("invalid label {:?}", label)));
+            }
+            if labels.insert(label.to_owned(), lines.len()).is_some() {
+                return Err(error(format!("duplicate label {:?}", label)));
+            }
+            code = rest.trim();
+        }
+        let words: Vec<&str> = code.split_whitespace().collect();
+        if let [op, operands @ ..] = &words[..] {
+            lines.push((line_nr, *op, operands.to_vec()));
+        }
+    }
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(addr, (line_nr, op, operands))| {
+            let offset = |operand: &str| match (operand.parse::<i32>(), labels.get(operand)) {
+                (Ok(n), _) => Ok(n),
+                (Err(_), Some(target)) => Ok(*target as i32 - addr as i32),
+                (Err(_), None) if is_label(operand) => Err(format!("unknown label {:?}", operand)),
+                (Err(_), None) => Err(format!("invalid operand {:?}", operand)),
+            };
+            I::decode(op, operands, &offset).map_err(|message| AsmError {
+                line: *line_nr,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Prints a program with its addresses, and labels in place of jump offsets
+/// wherever the target lies within the program. The output assembles back into
+/// the same program.
+pub fn disassemble(instructions: &[Instruction]) -> String {
+    let len = instructions.len();
+    let width = len.to_string().len().max(4);
+    let target = |addr: usize, n: i32| {
+        let target = addr as i64 + n as i64;
+        match (0..=len as i64).contains(&target) {
+            true => Some(target as usize),
+            false => None,
+        }
+    };
+    let label = |addr: usize| match addr == len {
+        true => String::from("end"),
+        false => format!("l{:0width$}", addr, width = width),
+    };
+
+    let targets: HashSet<usize> = instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(addr, instruction)| match instruction {
+            Instruction::Jmp(n) => target(addr, *n),
+            _ => None,
+        })
+        .collect();
+
+    let mut text = String::new();
+    for (addr, instruction) in instructions.iter().enumerate() {
+        if targets.contains(&addr) {
+            writeln!(text, "{}:", label(addr)).unwrap();
+        }
+        let code = match (instruction, target(addr, instruction.operand())) {
+            (Instruction::Jmp(_), Some(to)) => format!("jmp {}", label(to)),
+            _ => instruction.to_string(),
+        };
+        writeln!(text, "    {:<16}; {:0width$}", code, addr, width = width).unwrap();
+    }
+    if targets.contains(&len) {
+        writeln!(text, "{}:", label(len)).unwrap();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn labels() {
+        let source = "
+            # count to three
+            start:  acc +1
+                    nop start   ; never taken
+            loop:
+                    acc +2
+                    jmp done
+                    jmp loop
+            done:   jmp +1
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![Acc(1), Nop(-1), Acc(2), Jmp(2), Jmp(-2), Jmp(1)]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let err = |source| assemble(source).unwrap_err().to_string();
+        assert_eq!(
+            err("acc +1\njmp nowhere"),
+            "line 2: unknown label \"nowhere\""
+        );
+        assert_eq!(err("a: nop +0\na: nop +0"), "line 2: duplicate label \"a\"");
+        assert_eq!(err("mul +2"), "line 1: unknown instruction \"mul\"");
+        assert_eq!(
+            err("acc loop\nloop: nop +0"),
+            "line 1: invalid operand \"loop\""
+        );
+        assert_eq!(err("jmp"), "line 1: missing operand for \"jmp\"");
+        assert_eq!(err("2x: jmp +0"), "line 1: invalid label \"2x\"");
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = vec![
+            Nop(0),
+            Acc(1),
+            Jmp(4),
+            Acc(3),
+            Jmp(-3),
+            Acc(-99),
+            Acc(1),
+            Jmp(-4),
+            Acc(6),
+            Jmp(-100),
+        ];
+        let text = disassemble(&program);
+        assert!(text.contains("l0006:\n    acc +1          ; 0006\n"));
+        assert!(text.contains("    jmp -100        ; 0009\n"));
+        assert_eq!(assemble(&text).unwrap(), program);
+
+        let to_end = vec![Acc(1), Jmp(1)];
+        assert_eq!(
+            disassemble(&to_end),
+            "    acc +1          ; 0000\n    jmp end         ; 0001\nend:\n"
+        );
+        assert_eq!(assemble(&disassemble(&to_end)).unwrap(), to_end);
+    }
+}
@@ -0,0 +1,396 @@
+//! Control-flow graph of boot code, for looking at a program without running it.
+//!
+//! Boot code has no conditional jumps, so every basic block has exactly one
+//! successor and the path from the entry is fixed.
+
+use crate::Instruction;
+use std::fmt::Write;
+
+/// Where execution goes after a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Block(usize),
+    /// The address just past the last instruction.
+    End,
+    /// A jump to any other address outside the program. `Processor::run` treats
+    /// this as a fault, or as the end for jumps past it unless `Limits::exact_end`
+    /// is set.
+    Outside(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    /// One past the last instruction of the block.
+    pub end: usize,
+    pub successor: Target,
+}
+
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    /// The block each instruction belongs to.
+    pub block_of: Vec<usize>,
+    /// Whether a jump past the end is a fault, as with `Limits::exact_end`,
+    /// rather than the end of the program.
+    pub exact_end: bool,
+}
+
+impl Cfg {
+    pub fn new(instructions: &[Instruction], exact_end: bool) -> Self {
+        let len = instructions.len();
+        let target = |addr: usize, n: i32| addr as i64 + n as i64;
+
+        let mut leader = vec![false; len + 1];
+        leader[0] = true;
+        for (addr, instruction) in instructions.iter().enumerate() {
+            if let Instruction::Jmp(n) = instruction {
+                let to = target(addr, *n);
+                if (0..len as i64).contains(&to) {
+                    leader[to as usize] = true;
+                }
+                leader[addr + 1] = true;
+            }
+        }
+
+        let mut block_of = Vec::with_capacity(len);
+        let mut starts = Vec::new();
+        for (addr, leader) in leader[..len].iter().enumerate() {
+            if *leader {
+                starts.push(addr);
+            }
+            block_of.push(starts.len() - 1);
+        }
+
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(idx, &start)| {
+                let end = starts.get(idx + 1).copied().unwrap_or(len);
+                let next = match instructions[end - 1] {
+                    Instruction::Jmp(n) => target(end - 1, n),
+                    _ => end as i64,
+                };
+                let successor = match next {
+                    next if next == len as i64 => Target::End,
+                    next if (0..len as i64).contains(&next) => {
+                        Target::Block(block_of[next as usize])
+                    }
+                    next => Target::Outside(next),
+                };
+                Block {
+                    start,
+                    end,
+                    successor,
+                }
+            })
+            .collect();
+        Self {
+            blocks,
+            block_of,
+            exact_end,
+        }
+    }
+
+    /// Whether jumping to `addr` outside the program ends it.
+    fn ends_at(&self, addr: i64) -> bool {
+        addr > self.block_of.len() as i64 && !self.exact_end
+    }
+
+    /// The blocks executed from the entry, in order, and where that path leads:
+    /// the end, out of the program, or back to a block already on the path.
+    pub fn entry_path(&self) -> (Vec<usize>, Target) {
+        let mut path = Vec::new();
+        let mut on_path = vec![false; self.blocks.len()];
+        let mut next = match self.blocks.is_empty() {
+            true => Target::End,
+            false => Target::Block(0),
+        };
+        while let Target::Block(block) = next {
+            if on_path[block] {
+                break;
+            }
+            on_path[block] = true;
+            path.push(block);
+            next = self.blocks[block].successor;
+        }
+        (path, next)
+    }
+
+    /// Instructions that no execution from the entry reaches.
+    pub fn unreachable(&self) -> Vec<usize> {
+        let mut reachable = vec![false; self.block_of.len()];
+        for block in self.entry_path().0 {
+            let block = &self.blocks[block];
+            reachable[block.start..block.end]
+                .iter_mut()
+                .for_each(|reachable| *reachable = true);
+        }
+        (0..reachable.len())
+            .filter(|addr| !reachable[*addr])
+            .collect()
+    }
+
+    /// For every block, whether execution starting there reaches the end.
+    pub fn reaches_end(&self) -> Vec<bool> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        let mut stack = Vec::new();
+        for (idx, block) in self.blocks.iter().enumerate() {
+            match block.successor {
+                Target::Block(next) => predecessors[next].push(idx),
+                Target::End => stack.push(idx),
+                Target::Outside(addr) if self.ends_at(addr) => stack.push(idx),
+                Target::Outside(_) => (),
+            }
+        }
+        let mut reaches_end = vec![false; self.blocks.len()];
+        while let Some(block) = stack.pop() {
+            reaches_end[block] = true;
+            stack.extend(&predecessors[block]);
+        }
+        reaches_end
+    }
+
+    /// Every cycle of blocks. Without conditional jumps, each one is an infinite
+    /// loop once entered.
+    pub fn loops(&self) -> Vec<Vec<usize>> {
+        // 0: not visited yet, 1: on the current walk, 2: done
+        let mut state = vec![0u8; self.blocks.len()];
+        let mut loops = Vec::new();
+        for first in 0..self.blocks.len() {
+            let mut walk = Vec::new();
+            let mut next = Target::Block(first);
+            while let Target::Block(block) = next {
+                match state[block] {
+                    0 => {
+                        state[block] = 1;
+                        walk.push(block);
+                        next = self.blocks[block].successor;
+                    }
+                    1 => {
+                        let start = walk.iter().position(|b| *b == block).unwrap();
+                        loops.push(walk[start..].to_vec());
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+            for block in walk {
+                state[block] = 2;
+            }
+        }
+        loops
+    }
+
+    fn name(&self, block: usize) -> String {
+        let block = &self.blocks[block];
+        match block.end - block.start {
+            1 => format!("{:04}", block.start),
+            _ => format!("{:04}-{:04}", block.start, block.end - 1),
+        }
+    }
+
+    fn names(&self, blocks: impl Iterator<Item = usize>) -> String {
+        blocks
+            .map(|block| self.name(block))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    pub fn report(&self, instructions: &[Instruction]) -> String {
+        let mut report = String::new();
+        writeln!(
+            report,
+            "{} instructions in {} blocks",
+            instructions.len(),
+            self.blocks.len()
+        )
+        .unwrap();
+
+        let (path, exit) = self.entry_path();
+        let exit = match exit {
+            Target::End => String::from("reaches the end"),
+            Target::Outside(addr) if self.ends_at(addr) => {
+                format!("reaches the end by jumping to {}", addr)
+            }
+            Target::Outside(addr) => format!("jumps out of the program to {}", addr),
+            Target::Block(block) => format!("loops forever from {}", self.name(block)),
+        };
+        writeln!(report, "entry: runs {} blocks and {}", path.len(), exit).unwrap();
+
+        let mut unreachable = self.unreachable();
+        unreachable.dedup_by_key(|addr| self.block_of[*addr]);
+        writeln!(
+            report,
+            "unreachable blocks: {}",
+            self.names(unreachable.iter().map(|addr| self.block_of[*addr]))
+        )
+        .unwrap();
+
+        let reaches_end = self.reaches_end();
+        writeln!(
+            report,
+            "blocks reaching the end: {}",
+            self.names((0..self.blocks.len()).filter(|block| reaches_end[*block]))
+        )
+        .unwrap();
+
+        let outside = (0..self.blocks.len())
+            .filter(|block| matches!(self.blocks[*block].successor, Target::Outside(_)));
+        writeln!(report, "jumps out of the program: {}", self.names(outside)).unwrap();
+
+        writeln!(report, "infinite loops:").unwrap();
+        for cycle in self.loops() {
+            let entered = path.contains(&cycle[0]);
+            writeln!(
+                report,
+                "  {}{}",
+                cycle
+                    .iter()
+                    .map(|block| self.name(*block))
+                    .collect::<Vec<String>>()
+                    .join(" -> "),
+                match entered {
+                    true => " (entered from the start)",
+                    false => "",
+                }
+            )
+            .unwrap();
+        }
+        report
+    }
+
+    /// Renders the graph in Graphviz DOT format. Blocks on the entry path are
+    /// bold, blocks reaching the end green and blocks in a loop red.
+    pub fn to_dot(&self, instructions: &[Instruction]) -> String {
+        let (path, _) = self.entry_path();
+        let reaches_end = self.reaches_end();
+        let mut in_loop = vec![false; self.blocks.len()];
+        for block in self.loops().into_iter().flatten() {
+            in_loop[block] = true;
+        }
+
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for (idx, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for (addr, instruction) in instructions[block.start..block.end].iter().enumerate() {
+                write!(label, "{:04}: {}\\l", block.start + addr, instruction).unwrap();
+            }
+            let mut style = vec![];
+            if path.contains(&idx) {
+                style.push("style=\"filled,bold\"");
+            } else {
+                style.push("style=\"filled,dashed\"");
+            }
+            style.push(match (reaches_end[idx], in_loop[idx]) {
+                (true, _) => "fillcolor=palegreen",
+                (false, true) => "fillcolor=lightpink",
+                (false, false) => "fillcolor=white",
+            });
+            writeln!(
+                dot,
+                "    b{} [label=\"{}\", {}];",
+                idx,
+                label,
+                style.join(", ")
+            )
+            .unwrap();
+        }
+        dot.push_str("    end [shape=doublecircle];\n");
+        for (idx, block) in self.blocks.iter().enumerate() {
+            match block.successor {
+                Target::Block(next) => writeln!(dot, "    b{} -> b{};", idx, next),
+                Target::End => writeln!(dot, "    b{} -> end;", idx),
+                Target::Outside(addr) => writeln!(
+                    dot,
+                    "    out{} [shape=octagon, label=\"{}\"];\n    b{} -> out{};",
+                    idx, addr, idx, idx
+                ),
+            }
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::example;
+    use Instruction::*;
+
+    #[test]
+    fn blocks() {
+        let cfg = Cfg::new(&example(), false);
+        let blocks: Vec<(usize, usize, Target)> = cfg
+            .blocks
+            .iter()
+            .map(|block| (block.start, block.end, block.successor))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                (0, 1, Target::Block(1)),
+                (1, 3, Target::Block(4)),
+                (3, 5, Target::Block(1)),
+                (5, 6, Target::Block(4)),
+                (6, 8, Target::Block(2)),
+                (8, 9, Target::End),
+            ]
+        );
+        assert_eq!(cfg.entry_path(), (vec![0, 1, 4, 2], Target::Block(1)));
+        assert_eq!(cfg.unreachable(), vec![5, 8]);
+        assert_eq!(cfg.loops(), vec![vec![1, 4, 2]]);
+        assert_eq!(
+            cfg.reaches_end(),
+            vec![false, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn report() {
+        let mut program = example();
+        program[5] = Jmp(-20);
+        let cfg = Cfg::new(&program, false);
+        assert_eq!(
+            cfg.report(&program),
+            "9 instructions in 6 blocks
+entry: runs 4 blocks and loops forever from 0001-0002
+unreachable blocks: 0005, 0008
+blocks reaching the end: 0008
+jumps out of the program: 0005
+infinite loops:
+  0001-0002 -> 0006-0007 -> 0003-0004 (entered from the start)
+"
+        );
+
+        let dot = cfg.to_dot(&program);
+        assert!(dot.contains(
+            "    b1 [label=\"0001: acc +1\\l0002: jmp +4\\l\", style=\"filled,bold\", fillcolor=lightpink];\n"
+        ));
+        assert!(dot.contains("    b3 -> out3;\n"));
+        assert!(dot.contains("    b5 -> end;\n"));
+    }
+
+    #[test]
+    fn jump_past_the_end() {
+        use crate::repair::terminating;
+        let program = vec![Nop(0), Jmp(10), Jmp(-1)];
+        let cfg = Cfg::new(&program, false);
+        assert_eq!(
+            cfg.blocks.iter().map(|b| b.successor).collect::<Vec<_>>(),
+            vec![Target::Block(1), Target::Outside(11), Target::Block(1)]
+        );
+        assert_eq!(cfg.reaches_end(), vec![true, true, true]);
+        assert_eq!(terminating(&program), vec![true, true, true]);
+        assert!(cfg
+            .report(&program)
+            .contains("entry: runs 2 blocks and reaches the end by jumping to 11\n"));
+
+        let cfg = Cfg::new(&program, true);
+        assert_eq!(cfg.reaches_end(), vec![false, false, false]);
+        assert!(cfg
+            .report(&program)
+            .contains("entry: runs 2 blocks and jumps out of the program to 11\n"));
+    }
+}
@@ -0,0 +1,517 @@
+//! Line-oriented step debugger for the handheld, reading commands from stdin.
+//!
+//! Running into an instruction that was already executed stops the program like a
+//! breakpoint and starts loop detection afresh, so `continue` can be used to go
+//! around a loop one iteration at a time.
+//!
+//! Every step is recorded, so `back` and `seek` can move through the run in both
+//! directions, as far back as the last reset, detected loop, restored checkpoint
+//! or change made with `set`.
+
+use crate::history::{History, Snapshot};
+use crate::{Instruction, Processor, Terminate};
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeSet;
+use std::io::prelude::*;
+
+/// Steps between the processor copies kept by the history.
+const INTERVAL: usize = 1024;
+
+const HELP: &str = "commands:
+  step [N]         execute N instructions (default 1)
+  continue         run until a breakpoint, watchpoint, loop or the end
+  back [N]         undo N instructions (default 1)
+  seek STEP        go to the state after STEP instructions
+  checkpoint       save the current state
+  restore N        go back to checkpoint N
+  break ADDR       stop before executing ADDR
+  delete [ADDR]    remove a breakpoint, or all of them
+  watch [VALUE]    stop when acc changes, or when it becomes VALUE
+  unwatch          remove all watchpoints
+  print            show acc, pc and the current instruction
+  set acc|pc N     change a register
+  list [N]         show N instructions either side of pc (default 3)
+  info             show breakpoints, watchpoints and checkpoints
+  reset            restart the program
+  quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Change,
+    Equals(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Back(usize),
+    Seek(usize),
+    Checkpoint,
+    Restore(usize),
+    Break(usize),
+    Delete(Option<usize>),
+    Watch(Watch),
+    Unwatch,
+    Print,
+    SetAcc(i32),
+    SetPc(usize),
+    List(usize),
+    Info,
+    Reset,
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |word: &str| {
+            word.parse::<usize>()
+                .map_err(|_| anyhow!("invalid number {:?}", word))
+        };
+        let command = match words[..] {
+            ["step" | "s"] => Command::Step(1),
+            ["step" | "s", n] => Command::Step(number(n)?),
+            ["continue" | "c"] => Command::Continue,
+            ["back"] => Command::Back(1),
+            ["back", n] => Command::Back(number(n)?),
+            ["seek", step] => Command::Seek(number(step)?),
+            ["checkpoint"] => Command::Checkpoint,
+            ["restore", n] => Command::Restore(number(n)?),
+            ["break" | "b", addr] => Command::Break(number(addr)?),
+            ["delete" | "d"] => Command::Delete(None),
+            ["delete" | "d", addr] => Command::Delete(Some(number(addr)?)),
+            ["watch" | "w"] => Command::Watch(Watch::Change),
+            ["watch" | "w", value] => Command::Watch(Watch::Equals(value.parse()?)),
+            ["unwatch"] => Command::Unwatch,
+            ["print" | "p"] => Command::Print,
+            ["set", "acc", value] => Command::SetAcc(value.parse()?),
+            ["set", "pc", value] => Command::SetPc(number(value)?),
+            ["list" | "l"] => Command::List(3),
+            ["list" | "l", n] => Command::List(number(n)?),
+            ["info" | "i"] => Command::Info,
+            ["reset"] => Command::Reset,
+            ["help" | "h" | "?"] => Command::Help,
+            ["quit" | "q"] => Command::Quit,
+            _ => bail!("unknown command {:?}, try help", line.trim()),
+        };
+        Ok(command)
+    }
+}
+
+/// Why execution stopped before the requested number of steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(usize),
+    Watchpoint { old: i32, new: i32 },
+    Terminated(Terminate),
+}
+
+pub struct Debugger<'a> {
+    instructions: &'a [Instruction],
+    history: History<'a>,
+    breakpoints: BTreeSet<usize>,
+    /// Set after stopping at a breakpoint, so that continuing executes the
+    /// instruction there instead of stopping again.
+    skip_breakpoint: bool,
+    watches: Vec<Watch>,
+    checkpoints: Vec<Snapshot>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(instructions: &'a [Instruction]) -> Self {
+        Self {
+            instructions,
+            history: History::new(instructions, INTERVAL),
+            breakpoints: BTreeSet::new(),
+            skip_breakpoint: false,
+            watches: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    fn processor(&self) -> &Processor {
+        &self.history.machine.processor
+    }
+
+    pub fn reset(&mut self) {
+        self.history = History::new(self.instructions, INTERVAL);
+        self.skip_breakpoint = false;
+    }
+
+    /// Executes one instruction, unless the program has ended, there is a
+    /// breakpoint on it or it was executed before.
+    pub fn step(&mut self) -> Option<Stop> {
+        let pc = self.processor().prog_counter;
+        let skip_breakpoint = std::mem::take(&mut self.skip_breakpoint);
+        if self.breakpoints.contains(&pc) && !skip_breakpoint {
+            self.skip_breakpoint = true;
+            return Some(Stop::Breakpoint(pc));
+        }
+        let old = self.processor().acc;
+        match self.history.forward() {
+            Some(Terminate::InfiniteLoop(acc)) => {
+                self.history.machine.forget_executed();
+                self.history.rebase();
+                return Some(Stop::Terminated(Terminate::InfiniteLoop(acc)));
+            }
+            // The instruction didn't run, so trying again stops the same way.
+            Some(terminate) => return Some(Stop::Terminated(terminate)),
+            None => (),
+        }
+        let new = self.processor().acc;
+        let watched = self.watches.iter().any(|watch| match watch {
+            Watch::Change => new != old,
+            Watch::Equals(value) => new != old && new == *value,
+        });
+        match watched {
+            true => Some(Stop::Watchpoint { old, new }),
+            false => None,
+        }
+    }
+
+    /// Steps `n` times, or until something stops execution. `None` means the
+    /// program can run on. A breakpoint at the current instruction doesn't stop
+    /// the first step.
+    pub fn step_n(&mut self, n: usize) -> Option<Stop> {
+        self.skip_breakpoint = true;
+        (0..n).find_map(|_| self.step())
+    }
+
+    pub fn resume(&mut self) -> Stop {
+        loop {
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+        }
+    }
+
+    fn location(&self) -> String {
+        let processor = self.processor();
+        let pc = processor.prog_counter;
+        match self.instructions.get(pc) {
+            Some(instruction) => format!(
+                "{:04}: {:<10} acc {}",
+                pc,
+                instruction.to_string(),
+                processor.acc
+            ),
+            None => format!("{:04}: <end>      acc {}", pc, processor.acc),
+        }
+    }
+
+    fn list(&self, around: usize, output: &mut impl Write) -> Result<()> {
+        let pc = self.processor().prog_counter;
+        let start = pc.saturating_sub(around).min(self.instructions.len());
+        let end = pc.saturating_add(around + 1).min(self.instructions.len());
+        for addr in start..end {
+            let marker = match (addr == pc, self.breakpoints.contains(&addr)) {
+                (true, _) => "=>",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            writeln!(
+                output,
+                "{} {:04}  {}",
+                marker, addr, self.instructions[addr]
+            )?;
+        }
+        if pc >= self.instructions.len() {
+            writeln!(output, "=> {:04}  <end>", pc)?;
+        }
+        Ok(())
+    }
+
+    /// Runs one command, returning false once the user quits.
+    pub fn execute(&mut self, command: Command, output: &mut impl Write) -> Result<bool> {
+        let stop = match command {
+            Command::Step(n) => self.step_n(n),
+            Command::Continue => Some(self.resume()),
+            Command::Back(n) => {
+                if !(0..n).all(|_| self.history.back()) {
+                    writeln!(output, "reached the start of the recording")?;
+                }
+                self.skip_breakpoint = false;
+                None
+            }
+            Command::Seek(step) => {
+                self.history.seek(step);
+                let steps = self.history.machine.steps;
+                if steps != step {
+                    writeln!(output, "stopped at step {}", steps)?;
+                }
+                self.skip_breakpoint = false;
+                None
+            }
+            Command::Checkpoint => {
+                self.checkpoints.push(self.history.machine.checkpoint());
+                writeln!(
+                    output,
+                    "checkpoint {} at step {}",
+                    self.checkpoints.len() - 1,
+                    self.history.machine.steps
+                )?;
+                return Ok(true);
+            }
+            Command::Restore(n) => {
+                let snapshot = match self.checkpoints.get(n) {
+                    Some(snapshot) => snapshot,
+                    None => {
+                        writeln!(output, "no checkpoint {}", n)?;
+                        return Ok(true);
+                    }
+                };
+                self.history.machine.restore(snapshot);
+                self.history.rebase();
+                self.skip_breakpoint = false;
+                None
+            }
+            Command::Break(addr) => {
+                if addr >= self.instructions.len() {
+                    writeln!(output, "address {} is outside the program", addr)?;
+                } else if self.breakpoints.insert(addr) {
+                    writeln!(output, "breakpoint at {:04}", addr)?;
+                }
+                return Ok(true);
+            }
+            Command::Delete(Some(addr)) => {
+                if !self.breakpoints.remove(&addr) {
+                    writeln!(output, "no breakpoint at {:04}", addr)?;
+                }
+                return Ok(true);
+            }
+            Command::Delete(None) => {
+                self.breakpoints.clear();
+                return Ok(true);
+            }
+            Command::Watch(watch) => {
+                self.watches.push(watch);
+                return Ok(true);
+            }
+            Command::Unwatch => {
+                self.watches.clear();
+                return Ok(true);
+            }
+            Command::Print => None,
+            Command::SetAcc(value) => {
+                self.history.machine.processor.acc = value;
+                self.history.rebase();
+                None
+            }
+            Command::SetPc(addr) => {
+                if addr > self.instructions.len() {
+                    writeln!(output, "address {} is outside the program", addr)?;
+                    return Ok(true);
+                }
+                self.history.machine.processor.prog_counter = addr;
+                self.history.rebase();
+                self.skip_breakpoint = false;
+                None
+            }
+            Command::List(around) => {
+                self.list(around, output)?;
+                return Ok(true);
+            }
+            Command::Info => {
+                writeln!(output, "{} steps executed", self.history.machine.steps)?;
+                for addr in &self.breakpoints {
+                    writeln!(output, "breakpoint at {:04}", addr)?;
+                }
+                for watch in &self.watches {
+                    match watch {
+                        Watch::Change => writeln!(output, "watching acc for changes")?,
+                        Watch::Equals(value) => writeln!(output, "watching acc for {}", value)?,
+                    }
+                }
+                for (n, snapshot) in self.checkpoints.iter().enumerate() {
+                    writeln!(output, "checkpoint {} at step {}", n, snapshot.steps)?;
+                }
+                return Ok(true);
+            }
+            Command::Reset => {
+                self.reset();
+                None
+            }
+            Command::Help => {
+                writeln!(output, "{}", HELP)?;
+                return Ok(true);
+            }
+            Command::Quit => return Ok(false),
+        };
+
+        match stop {
+            Some(Stop::Breakpoint(addr)) => writeln!(output, "breakpoint at {:04}", addr)?,
+            Some(Stop::Watchpoint { old, new }) => {
+                writeln!(output, "acc changed from {} to {}", old, new)?
+            }
+            Some(Stop::Terminated(Terminate::InfiniteLoop(_))) => writeln!(
+                output,
+                "loop detected: {:04} was executed before",
+                self.processor().prog_counter
+            )?,
+            Some(Stop::Terminated(Terminate::EndOfCode(_))) => {
+                writeln!(output, "program finished")?
+            }
+            Some(Stop::Terminated(terminate)) => {
+                writeln!(output, "program stopped: {:?}", terminate)?
+            }
+            None => (),
+        }
+        writeln!(output, "{}", self.location())?;
+        Ok(true)
+    }
+
+    /// Reads commands from `input` until it ends or the user quits.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> Result<()> {
+        writeln!(output, "{}", self.location())?;
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(day8) ")?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match Command::parse(&line) {
+                Ok(command) => {
+                    if !self.execute(command, &mut output)? {
+                        return Ok(());
+                    }
+                }
+                Err(err) => writeln!(output, "error: {}", err)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::example;
+    use Instruction::*;
+
+    #[test]
+    fn breakpoints_and_loops() {
+        let program = example();
+        let mut debugger = Debugger::new(&program);
+        debugger.breakpoints.insert(3);
+        assert_eq!(debugger.resume(), Stop::Breakpoint(3));
+        assert_eq!(debugger.processor().acc, 2);
+        assert_eq!(
+            debugger.resume(),
+            Stop::Terminated(Terminate::InfiniteLoop(5))
+        );
+        assert_eq!(debugger.processor().prog_counter, 1);
+        // Loop detection starts over, so the next iteration runs up to the breakpoint.
+        assert_eq!(debugger.resume(), Stop::Breakpoint(3));
+        assert_eq!(debugger.history.machine.steps, 11);
+
+        // Breakpoints stop before the instruction, also at the very start and
+        // right after moving the program counter onto one.
+        debugger.reset();
+        debugger.breakpoints.insert(0);
+        assert_eq!(debugger.resume(), Stop::Breakpoint(0));
+        assert_eq!(debugger.history.machine.steps, 0);
+        assert_eq!(debugger.resume(), Stop::Breakpoint(3));
+        let steps = debugger.history.machine.steps;
+        debugger
+            .execute(Command::SetPc(0), &mut Vec::new())
+            .unwrap();
+        assert_eq!(debugger.resume(), Stop::Breakpoint(0));
+        assert_eq!(debugger.history.machine.steps, steps);
+
+        let mut patched = program;
+        patched[7] = Nop(-4);
+        assert_eq!(
+            Debugger::new(&patched).resume(),
+            Stop::Terminated(Terminate::EndOfCode(8))
+        );
+    }
+
+    #[test]
+    fn faults_stay_put() {
+        let program = [Acc(1), Jmp(-5)];
+        let mut debugger = Debugger::new(&program);
+        let fault = Stop::Terminated(Terminate::Fault { acc: 1, target: -4 });
+        assert_eq!(debugger.resume(), fault);
+        assert_eq!(debugger.resume(), fault);
+        assert_eq!(debugger.processor().prog_counter, 1);
+        assert_eq!(debugger.history.machine.steps, 1);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let program = example();
+        let mut debugger = Debugger::new(&program);
+        debugger.watches.push(Watch::Equals(5));
+        assert_eq!(debugger.step_n(3), None);
+        assert_eq!(debugger.resume(), Stop::Watchpoint { old: 2, new: 5 });
+        assert_eq!(debugger.processor().prog_counter, 4);
+    }
+
+    #[test]
+    fn session() {
+        let script = "break 4\ncontinue\nset acc 40\nstep\nstep\nlist\nfly\nset pc -1\nset pc 10\nquit\nprint\n";
+        let mut output = Vec::new();
+        Debugger::new(&example())
+            .run(script.as_bytes(), &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "0000: nop +0     acc 0
+(day8) breakpoint at 0004
+(day8) breakpoint at 0004
+0004: jmp -3     acc 5
+(day8) 0004: jmp -3     acc 40
+(day8) 0001: acc +1     acc 40
+(day8) loop detected: 0001 was executed before
+0001: acc +1     acc 40
+(day8)    0000  nop +0
+=> 0001  acc +1
+   0002  jmp +4
+   0003  acc +3
+ * 0004  jmp -3
+(day8) error: unknown command \"fly\", try help
+(day8) error: invalid number \"-1\"
+(day8) address 10 is outside the program
+(day8) "
+        );
+    }
+
+    #[test]
+    fn time_travel() {
+        let script = "break 4\nstep 3\ncheckpoint\ncontinue\nback 2\nseek 1\nback 5\nrestore 0\nset acc 7\nback\nrestore 1\ninfo\n";
+        let mut output = Vec::new();
+        Debugger::new(&example())
+            .run(script.as_bytes(), &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "0000: nop +0     acc 0
+(day8) breakpoint at 0004
+(day8) 0006: acc +1     acc 1
+(day8) checkpoint 0 at step 3
+(day8) breakpoint at 0004
+0004: jmp -3     acc 5
+(day8) 0007: jmp -4     acc 2
+(day8) 0001: acc +1     acc 0
+(day8) reached the start of the recording
+0000: nop +0     acc 0
+(day8) 0006: acc +1     acc 1
+(day8) 0006: acc +1     acc 7
+(day8) reached the start of the recording
+0006: acc +1     acc 7
+(day8) no checkpoint 1
+(day8) 3 steps executed
+breakpoint at 0004
+checkpoint 0 at step 3
+(day8) "
+        );
+    }
+}
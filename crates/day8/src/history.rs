@@ -0,0 +1,274 @@
+//! Checkpoints and time travel for boot code runs.
+//!
+//! A `History` keeps a small undo record per executed instruction, which is
+//! enough to step backwards, and a copy of the processor every `interval` steps,
+//! so that jumping to any step only re-executes at most `interval` instructions.
+//! The executed instructions are not copied: the undo records between two
+//! points say which ones to mark or clear.
+
+use crate::{Instruction, Processor, Terminate};
+
+/// Everything needed to continue a run from some point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub processor: Processor,
+    pub executed: Vec<bool>,
+    pub steps: usize,
+}
+
+/// A run of a program that can be stopped after any instruction.
+pub struct Machine<'a> {
+    instructions: &'a [Instruction],
+    pub processor: Processor,
+    executed: Vec<bool>,
+    /// Instructions executed so far.
+    pub steps: usize,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(instructions: &'a [Instruction]) -> Self {
+        Self {
+            instructions,
+            processor: Processor::new(),
+            executed: vec![false; instructions.len()],
+            steps: 0,
+        }
+    }
+
+    /// Executes one instruction, or returns why the program stops instead, in
+    /// which case nothing changed.
+    pub fn step(&mut self) -> Option<Terminate> {
+        let pc = self.processor.prog_counter;
+        let len = self.instructions.len();
+        if pc >= len {
+            return Some(Terminate::EndOfCode(self.processor.acc));
+        }
+        if self.executed[pc] {
+            return Some(Terminate::InfiniteLoop(self.processor.acc));
+        }
+        if let Some(terminate) = self.processor.step(self.instructions[pc], len) {
+            return Some(terminate);
+        }
+        self.executed[pc] = true;
+        self.steps += 1;
+        None
+    }
+
+    pub fn checkpoint(&self) -> Snapshot {
+        Snapshot {
+            processor: self.processor.clone(),
+            executed: self.executed.clone(),
+            steps: self.steps,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.processor.clone_from(&snapshot.processor);
+        self.executed.clone_from(&snapshot.executed);
+        self.steps = snapshot.steps;
+    }
+
+    /// Forgets which instructions ran, so running one again isn't a loop.
+    pub fn forget_executed(&mut self) {
+        self.executed
+            .iter_mut()
+            .for_each(|executed| *executed = false);
+    }
+}
+
+/// What an instruction changed, so it can be undone.
+#[derive(Debug, Clone, Copy)]
+struct Delta {
+    pc: usize,
+    acc: i32,
+}
+
+pub struct History<'a> {
+    pub machine: Machine<'a>,
+    /// The step recording started at, which is as far back as we can go.
+    base: usize,
+    /// `deltas[i]` undoes step `base + i + 1`. Stepping back keeps them, so the
+    /// steps can be redone.
+    deltas: Vec<Delta>,
+    /// `snapshots[i]` is the processor after `base + i * interval` steps.
+    snapshots: Vec<Processor>,
+    interval: usize,
+}
+
+impl<'a> History<'a> {
+    pub fn new(instructions: &'a [Instruction], interval: usize) -> Self {
+        let machine = Machine::new(instructions);
+        let snapshots = vec![machine.processor.clone()];
+        Self {
+            machine,
+            base: 0,
+            deltas: Vec::new(),
+            snapshots,
+            interval: interval.max(1),
+        }
+    }
+
+    /// The last step recorded so far, including ones stepped back over.
+    pub fn len(&self) -> usize {
+        self.base + self.deltas.len()
+    }
+
+    /// Starts recording afresh from the current state. Needed whenever the
+    /// machine changes other than by stepping, since the old records no longer
+    /// lead here.
+    pub fn rebase(&mut self) {
+        self.base = self.machine.steps;
+        self.deltas.clear();
+        self.snapshots.clear();
+        self.snapshots.push(self.machine.processor.clone());
+    }
+
+    pub fn forward(&mut self) -> Option<Terminate> {
+        let before = Delta {
+            pc: self.machine.processor.prog_counter,
+            acc: self.machine.processor.acc,
+        };
+        if let Some(terminate) = self.machine.step() {
+            return Some(terminate);
+        }
+        let steps = self.machine.steps;
+        if steps > self.len() {
+            self.deltas.push(before);
+            if (steps - self.base) % self.interval == 0 {
+                self.snapshots.push(self.machine.processor.clone());
+            }
+        }
+        None
+    }
+
+    /// Runs forward until the program stops.
+    pub fn run(&mut self) -> Terminate {
+        loop {
+            if let Some(terminate) = self.forward() {
+                return terminate;
+            }
+        }
+    }
+
+    /// Undoes the last step, returning false at the start of the recording.
+    pub fn back(&mut self) -> bool {
+        let steps = self.machine.steps;
+        if steps == self.base {
+            return false;
+        }
+        let delta = self.deltas[steps - self.base - 1];
+        let processor = &mut self.machine.processor;
+        processor.prog_counter = delta.pc;
+        processor.acc = delta.acc;
+        self.machine.executed[delta.pc] = false;
+        self.machine.steps -= 1;
+        true
+    }
+
+    /// Moves to the state after `step` steps, or as close as the recording and
+    /// the program allow.
+    pub fn seek(&mut self, step: usize) {
+        let step = step.max(self.base);
+        if step <= self.machine.steps && self.machine.steps - step < self.interval {
+            while self.machine.steps > step {
+                self.back();
+            }
+            return;
+        }
+        let snapshot = ((step - self.base) / self.interval).min(self.snapshots.len() - 1);
+        let target = self.base + snapshot * self.interval;
+        if step < self.machine.steps || target > self.machine.steps {
+            self.restore(snapshot);
+        }
+        while self.machine.steps < step && self.forward().is_none() {}
+    }
+
+    /// Moves to the state of `snapshots[snapshot]`.
+    fn restore(&mut self, snapshot: usize) {
+        let current = self.machine.steps - self.base;
+        let target = snapshot * self.interval;
+        let executed = &mut self.machine.executed;
+        if target < current {
+            for delta in &self.deltas[target..current] {
+                executed[delta.pc] = false;
+            }
+        } else {
+            for delta in &self.deltas[current..target] {
+                executed[delta.pc] = true;
+            }
+        }
+        self.machine.processor.clone_from(&self.snapshots[snapshot]);
+        self.machine.steps = self.base + target;
+    }
+
+    /// Finds the last step up to the current one that changed `acc` to `value`,
+    /// and moves to the state right after it.
+    pub fn find_acc(&mut self, value: i32) -> Option<usize> {
+        let current = self.machine.steps;
+        let acc_after = |step: usize| match step == current {
+            true => self.machine.processor.acc,
+            false => self.deltas[step - self.base].acc,
+        };
+        let acc_before = |step: usize| self.deltas[step - self.base - 1].acc;
+        let step = (self.base + 1..=current)
+            .rev()
+            .find(|&step| acc_after(step) == value && acc_before(step) != value)?;
+        self.seek(step);
+        Some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::example;
+
+    fn after(instructions: &[Instruction], steps: usize) -> Snapshot {
+        let mut machine = Machine::new(instructions);
+        for _ in 0..steps {
+            machine.step();
+        }
+        machine.checkpoint()
+    }
+
+    #[test]
+    fn checkpoint_and_restore() {
+        let program = example();
+        let mut machine = Machine::new(&program);
+        machine.step();
+        machine.step();
+        let snapshot = machine.checkpoint();
+        while machine.step().is_none() {}
+        assert_eq!(machine.steps, 7);
+
+        machine.restore(&snapshot);
+        assert_eq!(machine.checkpoint(), after(&program, 2));
+        while machine.step().is_none() {}
+        assert_eq!(machine.step(), Some(Terminate::InfiniteLoop(5)));
+    }
+
+    #[test]
+    fn time_travel() {
+        let program = example();
+        let mut history = History::new(&program, 2);
+        assert_eq!(history.run(), Terminate::InfiniteLoop(5));
+        assert_eq!(history.len(), 7);
+
+        assert!(history.back());
+        assert_eq!(history.machine.checkpoint(), after(&program, 6));
+        for step in [0, 5, 1, 7, 3, 4] {
+            history.seek(step);
+            assert_eq!(history.machine.checkpoint(), after(&program, step));
+        }
+        while history.back() {}
+        assert_eq!(history.machine.checkpoint(), after(&program, 0));
+
+        history.seek(7);
+        // acc after each step: 0 1 1 2 2 5 5
+        assert_eq!(history.find_acc(2), Some(4));
+        assert_eq!(history.machine.processor.prog_counter, 7);
+        assert_eq!(history.find_acc(1), Some(2));
+        assert_eq!(history.find_acc(5), None);
+        assert_eq!(history.machine.steps, 2);
+    }
+}
@@ -0,0 +1,347 @@
+//! Instruction sets the processor can run.
+//!
+//! `Processor::run` only fetches instructions and asks them where execution goes
+//! next, so a new instruction set is a type implementing `Execute`, plus `Decode`
+//! to assemble it. The boot code set is the `Instruction` enum; newer firmware
+//! uses `Op`, whose opcodes live in the `FIRMWARE` table, where adding an
+//! instruction means adding a row.
+
+use crate::{Instruction, Processor};
+use std::fmt;
+
+/// Where execution continues after an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    /// Jump relative to the instruction.
+    Jump(i32),
+    /// Stop on purpose.
+    Halt,
+    /// Stop because a register over- or underflowed.
+    Overflow,
+}
+
+pub trait Execute: Copy {
+    /// Whether the path through the program depends on nothing but the program,
+    /// so that reaching an instruction a second time means it loops forever.
+    const STATIC_FLOW: bool;
+
+    /// Updates the registers and says where execution continues. The program
+    /// counter is left to the processor.
+    fn execute(&self, processor: &mut Processor) -> Flow;
+}
+
+pub trait Decode: Sized {
+    /// Builds an instruction from its mnemonic and operands. `offset` resolves a
+    /// jump target, a number or a label, to an offset from this instruction.
+    fn decode(
+        mnemonic: &str,
+        operands: &[&str],
+        offset: &dyn Fn(&str) -> Result<i32, String>,
+    ) -> Result<Self, String>;
+}
+
+impl Execute for Instruction {
+    const STATIC_FLOW: bool = true;
+
+    fn execute(&self, processor: &mut Processor) -> Flow {
+        match *self {
+            Instruction::Acc(n) => set_acc(processor, processor.acc.checked_add(n)),
+            Instruction::Jmp(n) => Flow::Jump(n),
+            Instruction::Nop(_) => Flow::Next,
+        }
+    }
+}
+
+fn single<'a>(mnemonic: &str, operands: &[&'a str]) -> Result<&'a str, String> {
+    match operands {
+        [operand] => Ok(operand),
+        [] => Err(format!("missing operand for {:?}", mnemonic)),
+        _ => Err(format!("too many operands for {:?}", mnemonic)),
+    }
+}
+
+impl Decode for Instruction {
+    fn decode(
+        mnemonic: &str,
+        operands: &[&str],
+        offset: &dyn Fn(&str) -> Result<i32, String>,
+    ) -> Result<Self, String> {
+        match mnemonic {
+            "acc" => {
+                let operand = single(mnemonic, operands)?;
+                operand
+                    .parse()
+                    .map(Instruction::Acc)
+                    .map_err(|_| format!("invalid operand {:?}", operand))
+            }
+            "jmp" => offset(single(mnemonic, operands)?).map(Instruction::Jmp),
+            "nop" => offset(single(mnemonic, operands)?).map(Instruction::Nop),
+            _ => Err(format!("unknown instruction {:?}", mnemonic)),
+        }
+    }
+}
+
+/// The registers next to `acc`, which firmware programs refer to by name.
+pub const REGISTERS: [&str; 3] = ["b", "c", "d"];
+
+/// What kind of operand an opcode takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    None,
+    Value,
+    Offset,
+    /// An index into `REGISTERS`.
+    Register,
+}
+
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub operand: Operand,
+    pub execute: fn(&mut Processor, i32) -> Flow,
+}
+
+fn set_acc(processor: &mut Processor, value: Option<i32>) -> Flow {
+    match value {
+        Some(value) => {
+            processor.acc = value;
+            Flow::Next
+        }
+        None => Flow::Overflow,
+    }
+}
+
+pub static FIRMWARE: &[Opcode] = &[
+    Opcode {
+        mnemonic: "acc",
+        operand: Operand::Value,
+        execute: |p, n| set_acc(p, p.acc.checked_add(n)),
+    },
+    Opcode {
+        mnemonic: "jmp",
+        operand: Operand::Offset,
+        execute: |_, n| Flow::Jump(n),
+    },
+    Opcode {
+        mnemonic: "nop",
+        operand: Operand::Offset,
+        execute: |_, _| Flow::Next,
+    },
+    Opcode {
+        mnemonic: "jz",
+        operand: Operand::Offset,
+        execute: |p, n| match p.acc {
+            0 => Flow::Jump(n),
+            _ => Flow::Next,
+        },
+    },
+    Opcode {
+        mnemonic: "jnz",
+        operand: Operand::Offset,
+        execute: |p, n| match p.acc {
+            0 => Flow::Next,
+            _ => Flow::Jump(n),
+        },
+    },
+    Opcode {
+        mnemonic: "add",
+        operand: Operand::Register,
+        execute: |p, r| set_acc(p, p.acc.checked_add(p.registers[r as usize])),
+    },
+    Opcode {
+        mnemonic: "mul",
+        operand: Operand::Register,
+        execute: |p, r| set_acc(p, p.acc.checked_mul(p.registers[r as usize])),
+    },
+    Opcode {
+        mnemonic: "ld",
+        operand: Operand::Register,
+        execute: |p, r| set_acc(p, Some(p.registers[r as usize])),
+    },
+    Opcode {
+        mnemonic: "st",
+        operand: Operand::Register,
+        execute: |p, r| {
+            p.registers[r as usize] = p.acc;
+            Flow::Next
+        },
+    },
+    Opcode {
+        mnemonic: "out",
+        operand: Operand::None,
+        execute: |p, _| {
+            p.output.push(p.acc);
+            Flow::Next
+        },
+    },
+    Opcode {
+        mnemonic: "hlt",
+        operand: Operand::None,
+        execute: |_, _| Flow::Halt,
+    },
+];
+
+/// A firmware instruction: an opcode from `FIRMWARE` and its operand.
+#[derive(Clone, Copy)]
+pub struct Op {
+    pub opcode: &'static Opcode,
+    pub operand: i32,
+}
+
+impl PartialEq for Op {
+    fn eq(&self, other: &Self) -> bool {
+        self.opcode.mnemonic == other.opcode.mnemonic && self.operand == other.operand
+    }
+}
+
+impl Eq for Op {}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.opcode.operand {
+            Operand::None => write!(f, "{}", self.opcode.mnemonic),
+            Operand::Value | Operand::Offset => {
+                write!(f, "{} {:+}", self.opcode.mnemonic, self.operand)
+            }
+            Operand::Register => write!(
+                f,
+                "{} {}",
+                self.opcode.mnemonic, REGISTERS[self.operand as usize]
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Op({})", self)
+    }
+}
+
+impl Execute for Op {
+    // Conditional jumps can leave a loop once the registers change, so loops are
+    // found by comparing whole processor states instead.
+    const STATIC_FLOW: bool = false;
+
+    fn execute(&self, processor: &mut Processor) -> Flow {
+        (self.opcode.execute)(processor, self.operand)
+    }
+}
+
+impl Decode for Op {
+    fn decode(
+        mnemonic: &str,
+        operands: &[&str],
+        offset: &dyn Fn(&str) -> Result<i32, String>,
+    ) -> Result<Self, String> {
+        let opcode = FIRMWARE
+            .iter()
+            .find(|opcode| opcode.mnemonic == mnemonic)
+            .ok_or_else(|| format!("unknown instruction {:?}", mnemonic))?;
+        let operand = match opcode.operand {
+            Operand::None if operands.is_empty() => 0,
+            Operand::None => return Err(format!("{:?} takes no operand", mnemonic)),
+            Operand::Value => {
+                let operand = single(mnemonic, operands)?;
+                operand
+                    .parse()
+                    .map_err(|_| format!("invalid operand {:?}", operand))?
+            }
+            Operand::Offset => offset(single(mnemonic, operands)?)?,
+            Operand::Register => {
+                let operand = single(mnemonic, operands)?;
+                REGISTERS
+                    .iter()
+                    .position(|register| *register == operand)
+                    .ok_or_else(|| format!("unknown register {:?}", operand))?
+                    as i32
+            }
+        };
+        Ok(Op { opcode, operand })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_with;
+    use crate::Terminate;
+
+    const FACTORIAL: &str = "
+                acc +5
+                st b        # counter
+                acc -4
+                st c        # product
+        loop:   ld b
+                jz done
+                ld c
+                mul b
+                st c
+                ld b
+                acc -1
+                st b
+                jmp loop
+        done:   ld c
+                out
+                hlt
+                acc +1      # never reached
+    ";
+
+    #[test]
+    fn firmware() {
+        let program: Vec<Op> = assemble_with(FACTORIAL).unwrap();
+        assert_eq!(program[5].to_string(), "jz +8");
+        assert_eq!(program[7].to_string(), "mul b");
+
+        let mut processor = Processor::new();
+        assert_eq!(processor.run(&program), Terminate::Halted(120));
+        assert_eq!(processor.output, vec![120]);
+        assert_eq!(processor.registers, [0, 120, 0]);
+        assert_eq!(processor.prog_counter, 15);
+    }
+
+    #[test]
+    fn boot_code_overflow() {
+        use Instruction::*;
+        let mut processor = Processor::new();
+        let program = [Acc(i32::MAX), Acc(1), Acc(-5)];
+        assert_eq!(processor.run(&program), Terminate::Overflow(i32::MAX));
+        assert_eq!(processor.prog_counter, 1);
+        assert_eq!(
+            Processor::new().run(&[Acc(i32::MIN), Acc(-1)]),
+            Terminate::Overflow(i32::MIN)
+        );
+    }
+
+    #[test]
+    fn firmware_loops() {
+        let run = |source| Processor::new().run(&assemble_with::<Op>(source).unwrap());
+        assert_eq!(run("loop: jmp loop"), Terminate::InfiniteLoop(0));
+        // Counts b down from 3 and then loops forever with acc 0.
+        let source = "acc +3\nst b\nloop: ld b\njz loop\nacc -1\nst b\njmp loop";
+        assert_eq!(run(source), Terminate::InfiniteLoop(0));
+        // Never repeats a state, until acc overflows.
+        assert_eq!(
+            run("loop: acc +1000000\njmp loop"),
+            Terminate::Overflow(2147000000)
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let program: Vec<Op> = assemble_with("acc +65536\nst b\nmul b\nout").unwrap();
+        let mut processor = Processor::new();
+        assert_eq!(processor.run(&program), Terminate::Overflow(65536));
+        assert_eq!(processor.prog_counter, 2);
+        assert!(processor.output.is_empty());
+    }
+
+    #[test]
+    fn decode_errors() {
+        let err = |source| assemble_with::<Op>(source).unwrap_err().to_string();
+        assert_eq!(err("ld e"), "line 1: unknown register \"e\"");
+        assert_eq!(err("hlt +1"), "line 1: \"hlt\" takes no operand");
+        assert_eq!(err("jz"), "line 1: missing operand for \"jz\"");
+        assert_eq!(err("div b"), "line 1: unknown instruction \"div\"");
+    }
+}
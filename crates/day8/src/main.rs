@@ -1,88 +1,218 @@
-use anyhow::Result;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::time::Instant;
+use anyhow::{anyhow, bail, ensure, Result};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{stdin, stdout};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone)]
+mod asm;
+mod cfg;
+mod debugger;
+mod fast;
+mod history;
+mod isa;
+mod repair;
+mod trace;
+
+use asm::{assemble, assemble_with, disassemble};
+use cfg::Cfg;
+use debugger::Debugger;
+use fast::Decoded;
+use history::History;
+use isa::{Execute, Flow, Op};
+use repair::{repair, repairs};
+use trace::{replay, to_json_lines, TraceStep, Tracer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Instruction {
     Acc(i32),
     Jmp(i32),
     Nop(i32),
 }
 
+impl Instruction {
+    fn operand(&self) -> i32 {
+        match self {
+            Instruction::Acc(n) | Instruction::Jmp(n) | Instruction::Nop(n) => *n,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Acc(n) => write!(f, "acc {:+}", n),
+            Instruction::Jmp(n) => write!(f, "jmp {:+}", n),
+            Instruction::Nop(n) => write!(f, "nop {:+}", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Terminate {
+    /// The program reached a state it was in before, so it would run forever.
+    /// For boot code the program counter alone is the state.
     InfiniteLoop(i32),
     EndOfCode(i32),
+    /// A `hlt` instruction stopped the program.
+    Halted(i32),
+    /// An instruction would have overflowed `acc`, which keeps its old value.
+    Overflow(i32),
+    /// A jump to `target` left the program anywhere but at its end. The program
+    /// counter stays on the jump.
+    Fault {
+        acc: i32,
+        target: i64,
+    },
+    /// `Limits::max_steps` instructions ran without the program stopping.
+    StepLimit(i32),
+    /// The program ran for longer than `Limits::timeout`.
+    TimeLimit(i32),
 }
 
-#[derive(Debug, Clone)]
+/// Restrictions on how a program may run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Limits {
+    /// Most instructions to execute in one run.
+    max_steps: Option<u64>,
+    /// Longest time one run may take.
+    timeout: Option<Duration>,
+    /// Only a jump to exactly the address after the last instruction ends the
+    /// program; jumping further is a fault too.
+    exact_end: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Processor {
     acc: i32,
     prog_counter: usize,
+    /// The registers named in `isa::REGISTERS`, which boot code doesn't use.
+    registers: [i32; 3],
+    /// Values written by `out` instructions.
+    output: Vec<i32>,
+    limits: Limits,
 }
 
 impl Processor {
     fn new() -> Self {
+        Self::with_limits(Limits::default())
+    }
+
+    fn with_limits(limits: Limits) -> Self {
         Self {
             acc: 0,
             prog_counter: 0,
+            registers: [0; 3],
+            output: Vec::new(),
+            limits,
         }
     }
 
     fn reset(&mut self) {
         self.acc = 0;
         self.prog_counter = 0;
+        self.registers = [0; 3];
+        self.output.clear();
+    }
+
+    /// Everything that decides what a program does next.
+    fn state(&self) -> (usize, i32, [i32; 3]) {
+        (self.prog_counter, self.acc, self.registers)
     }
 
-    fn run(&mut self, instructions: &[Instruction]) -> Terminate {
+    fn run<I: Execute>(&mut self, instructions: &[I]) -> Terminate {
+        self.run_traced(instructions, &mut ())
+    }
+
+    /// Like `run`, but hands every executed instruction to `tracer`.
+    fn run_traced<I: Execute>(
+        &mut self,
+        instructions: &[I],
+        tracer: &mut impl Tracer<I>,
+    ) -> Terminate {
+        let start = Instant::now();
         let mut executed = vec![false; instructions.len()];
+        // Brent's cycle detection for instruction sets whose flow depends on the
+        // registers: the state is compared with one saved at every power of two
+        // steps, which finds any cycle without remembering every state.
+        let mut saved = self.state();
+        let (mut power, mut since_saved) = (1u64, 0u64);
+        let mut steps = 0u64;
         loop {
-            if self.prog_counter >= instructions.len() {
+            let pc = self.prog_counter;
+            if pc >= instructions.len() {
                 return Terminate::EndOfCode(self.acc);
             }
-            if executed[self.prog_counter] {
-                return Terminate::InfiniteLoop(self.acc);
-            }
-            executed[self.prog_counter] = true;
-
-            match instructions[self.prog_counter] {
-                Instruction::Acc(n) => {
-                    self.acc += n;
-                    self.prog_counter += 1;
+            if I::STATIC_FLOW {
+                if executed[pc] {
+                    return Terminate::InfiniteLoop(self.acc);
                 }
-                Instruction::Jmp(n) => {
-                    self.prog_counter = (self.prog_counter as i32 + n) as usize;
+                executed[pc] = true;
+            } else {
+                let state = self.state();
+                if since_saved > 0 && state == saved {
+                    return Terminate::InfiniteLoop(self.acc);
                 }
-                Instruction::Nop(_) => {
-                    self.prog_counter += 1;
+                if since_saved == power {
+                    saved = state;
+                    power *= 2;
+                    since_saved = 0;
                 }
+                since_saved += 1;
+            }
+            if Some(steps) == self.limits.max_steps {
+                return Terminate::StepLimit(self.acc);
+            }
+            // Looking at the clock is slow compared to an instruction.
+            if steps % 4096 == 0 && self.limits.timeout.is_some_and(|t| start.elapsed() >= t) {
+                return Terminate::TimeLimit(self.acc);
+            }
+            steps += 1;
+
+            let acc_before = self.acc;
+            let halt = self.step(instructions[pc], instructions.len());
+            tracer.record(TraceStep {
+                pc,
+                instruction: instructions[pc],
+                acc_before,
+                acc_after: self.acc,
+            });
+            if let Some(terminate) = halt {
+                return terminate;
             }
         }
     }
-}
 
-fn read_input() -> Result<Vec<Instruction>> {
-    let file = File::open("input.txt")?;
-    let reader = BufReader::new(file);
-
-    let instructions: Vec<Instruction> = reader
-        .lines()
-        .map(|line| line.unwrap())
-        .map(
-            |line| match &line.split_whitespace().collect::<Vec<&str>>()[..] {
-                ["acc", operand] => Instruction::Acc(operand.parse().unwrap()),
-                ["jmp", operand] => Instruction::Jmp(operand.parse().unwrap()),
-                ["nop", operand] => Instruction::Nop(operand.parse().unwrap()),
-                _ => panic!("invalid input"),
-            },
-        )
-        .collect();
+    /// Executes a single instruction of a program `len` instructions long, which
+    /// the caller fetched from the program counter. Returns why the program
+    /// stopped if the instruction stopped it.
+    fn step<I: Execute>(&mut self, instruction: I, len: usize) -> Option<Terminate> {
+        match instruction.execute(self) {
+            Flow::Next => self.prog_counter += 1,
+            Flow::Jump(n) => {
+                let target = self.prog_counter as i64 + n as i64;
+                if target < 0 || (self.limits.exact_end && target > len as i64) {
+                    return Some(Terminate::Fault {
+                        acc: self.acc,
+                        target,
+                    });
+                }
+                self.prog_counter = target as usize;
+            }
+            Flow::Halt => return Some(Terminate::Halted(self.acc)),
+            Flow::Overflow => return Some(Terminate::Overflow(self.acc)),
+        }
+        None
+    }
+}
 
-    Ok(instructions)
+fn read_input(path: &str) -> Result<Vec<Instruction>> {
+    Ok(assemble(&fs::read_to_string(path)?)?)
 }
 
-fn brute_force(instructions: &mut [Instruction]) -> i32 {
+/// Flips each `jmp` and `nop` in turn until the program ends, and returns the
+/// accumulator it ends with.
+fn brute_force(instructions: &mut [Instruction]) -> Option<i32> {
     let mut processor = Processor::new();
 
     for i in 0..instructions.len() {
@@ -91,37 +221,285 @@ fn brute_force(instructions: &mut [Instruction]) -> i32 {
             Instruction::Jmp(n) => {
                 instructions[i] = Instruction::Nop(n);
                 processor.reset();
-                match processor.run(instructions) {
-                    Terminate::EndOfCode(acc) => return acc,
-                    Terminate::InfiniteLoop(_) => (),
+                if let Terminate::EndOfCode(acc) = processor.run(instructions) {
+                    return Some(acc);
                 }
                 instructions[i] = Instruction::Jmp(n);
             }
             Instruction::Nop(n) => {
                 instructions[i] = Instruction::Jmp(n);
                 processor.reset();
-                match processor.run(instructions) {
-                    Terminate::EndOfCode(acc) => return acc,
-                    Terminate::InfiniteLoop(_) => (),
+                if let Terminate::EndOfCode(acc) = processor.run(instructions) {
+                    return Some(acc);
                 }
                 instructions[i] = Instruction::Nop(n);
             }
         }
     }
-    panic!("no fix found")
+    None
+}
+
+/// Tiny xorshift generator, good enough for synthetic programs.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// Generates a boot program of about `len` instructions that loops. Restoring
+/// the last forward jump it takes, which was turned into a `nop`, makes it end;
+/// every other flip on the way there leads back into code that already ran.
+fn synthetic_program(len: usize, seed: u64) -> Vec<Instruction> {
+    let mut rng = Rng::new(seed);
+    let mut program = Vec::with_capacity(len + 5);
+    let mut last_jump = None;
+    while program.len() < len {
+        let addr = program.len();
+        match rng.next(4) {
+            0 => {
+                // Jump over a few traps that send execution back to earlier code.
+                let skip = rng.next(4) + 1;
+                last_jump = Some(addr);
+                program.push(Instruction::Jmp(skip as i32 + 1));
+                for trap in addr + 1..=addr + skip {
+                    program.push(Instruction::Jmp(-(rng.next(trap) as i32 + 1)));
+                }
+            }
+            1 => program.push(Instruction::Nop(-(rng.next(addr + 1) as i32))),
+            _ => program.push(Instruction::Acc(rng.next(100) as i32 - 50)),
+        }
+    }
+    if let Some(addr) = last_jump {
+        program[addr] = Instruction::Nop(program[addr].operand());
+    }
+    program
+}
+
+fn bench(len: usize) -> Result<()> {
+    let mut program = synthetic_program(len, 2020);
+
+    let start = Instant::now();
+    let repaired = repair(&program).ok_or_else(|| anyhow!("no repair found"))?;
+    println!(
+        "repaired {} instructions in {} us: {:04}: {} -> {}, acc {}",
+        program.len(),
+        start.elapsed().as_micros(),
+        repaired.address,
+        repaired.old,
+        repaired.new,
+        repaired.acc
+    );
+
+    let runs = 100;
+    let start = Instant::now();
+    let mut processor = Processor::new();
+    for _ in 0..runs {
+        processor.reset();
+        processor.run(&program);
+    }
+    let processor_time = start.elapsed().as_micros();
+    let start = Instant::now();
+    let mut decoded = Decoded::new(&program, false);
+    for _ in 0..runs {
+        decoded.run();
+    }
+    println!(
+        "{} runs: processor {} us, decoded {} us (including decoding)",
+        runs,
+        processor_time,
+        start.elapsed().as_micros()
+    );
+
+    if len > 20_000 {
+        println!("skipping brute force above 20000 instructions");
+        return Ok(());
+    }
+    let start = Instant::now();
+    let acc = decoded
+        .brute_force()
+        .ok_or_else(|| anyhow!("no repair found"))?;
+    let decoded_time = start.elapsed().as_micros();
+    let start = Instant::now();
+    let expected = brute_force(&mut program).ok_or_else(|| anyhow!("no repair found"))?;
+    ensure!(
+        acc == expected,
+        "decoded brute force found acc {}, processor found {}",
+        acc,
+        expected
+    );
+    println!(
+        "brute force: processor {} us, decoded {} us, acc {}",
+        start.elapsed().as_micros(),
+        decoded_time,
+        acc
+    );
+    Ok(())
+}
+
+/// Runs `instructions` and reports the last step that set `acc` to `value`.
+fn when(value: i32, instructions: &[Instruction]) -> Result<()> {
+    let mut history = History::new(instructions, 1024);
+    let result = history.run();
+    println!("{:?} after {} steps", result, history.len());
+    let step = history
+        .find_acc(value)
+        .ok_or_else(|| anyhow!("acc never became {}", value))?;
+    history.back();
+    let pc = history.machine.processor.prog_counter;
+    println!(
+        "acc became {} in step {}: {:04}: {}",
+        value, step, pc, instructions[pc]
+    );
+    Ok(())
+}
+
+/// Lists every repair of up to `max_changes` changes. `options` may hold
+/// `--acc`, to also change `acc` instructions, and the input file.
+fn list_repairs(max_changes: usize, options: &[&str]) -> Result<()> {
+    let (change_acc, path) = match options {
+        [] => (false, "input.txt"),
+        ["--acc"] => (true, "input.txt"),
+        [path] => (false, *path),
+        ["--acc", path] | [path, "--acc"] => (true, *path),
+        _ => bail!("usage: day8 repairs K [--acc] [FILE]"),
+    };
+    let found = repairs(&read_input(path)?, max_changes, change_acc);
+    for repair in &found {
+        let changes: Vec<String> = repair
+            .changes
+            .iter()
+            .map(|change| format!("{:04}: {} -> {}", change.address, change.old, change.new))
+            .collect();
+        println!("acc {:>6}  {}", repair.acc, changes.join(", "));
+    }
+    println!("{} repairs", found.len());
+    Ok(())
+}
+
+/// Prints the trace of running `instructions` as JSON Lines.
+fn trace(instructions: &[Instruction]) -> Result<()> {
+    let mut trace = Vec::new();
+    Processor::new().run_traced(instructions, &mut trace);
+    print!("{}", to_json_lines(&trace));
+    Ok(())
+}
+
+fn replay_file(path: &str, instructions: &[Instruction]) -> Result<()> {
+    let trace = trace::parse_json_lines(&fs::read_to_string(path)?)?;
+    let result = replay(instructions, &trace)?;
+    println!("trace of {} steps replayed, {:?}", trace.len(), result);
+    Ok(())
+}
+
+/// Runs a program written for the firmware instruction set. `options` may set
+/// `--max-steps N`, `--timeout-ms N` and `--exact-end`.
+fn run_firmware(path: &str, options: &[&str]) -> Result<()> {
+    let mut limits = Limits::default();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let mut value = || {
+            options
+                .next()
+                .ok_or_else(|| anyhow!("missing value for {}", option))
+        };
+        match *option {
+            "--max-steps" => limits.max_steps = Some(value()?.parse()?),
+            "--timeout-ms" => limits.timeout = Some(Duration::from_millis(value()?.parse()?)),
+            "--exact-end" => limits.exact_end = true,
+            _ => bail!("unknown option {}", option),
+        }
+    }
+
+    let program: Vec<Op> = assemble_with(&fs::read_to_string(path)?)?;
+    let mut processor = Processor::with_limits(limits);
+    let result = processor.run(&program);
+    for value in &processor.output {
+        println!("{}", value);
+    }
+    println!("{:?} at {:04}", result, processor.prog_counter);
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let start = Instant::now();
 
-    let mut instructions = read_input()?;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["asm", path] => {
+            for instruction in read_input(path)? {
+                println!("{}", instruction);
+            }
+            return Ok(());
+        }
+        ["disasm"] => {
+            print!("{}", disassemble(&read_input("input.txt")?));
+            return Ok(());
+        }
+        ["disasm", path] => {
+            print!("{}", disassemble(&read_input(path)?));
+            return Ok(());
+        }
+        ["debug"] => return Debugger::new(&read_input("input.txt")?).run(stdin().lock(), stdout()),
+        ["debug", path] => return Debugger::new(&read_input(path)?).run(stdin().lock(), stdout()),
+        ["cfg" | "dot", ref options @ ..] => {
+            let exact_end = options.contains(&"--exact-end");
+            let paths: Vec<&str> = options
+                .iter()
+                .copied()
+                .filter(|option| *option != "--exact-end")
+                .collect();
+            let path = match paths[..] {
+                [] => "input.txt",
+                [path] => path,
+                _ => bail!("usage: day8 {} [--exact-end] [FILE]", args[0]),
+            };
+            let instructions = read_input(path)?;
+            let cfg = Cfg::new(&instructions, exact_end);
+            match args[0] {
+                "cfg" => print!("{}", cfg.report(&instructions)),
+                _ => print!("{}", cfg.to_dot(&instructions)),
+            }
+            return Ok(());
+        }
+        ["repairs", max_changes, ref options @ ..] => {
+            return list_repairs(max_changes.parse()?, options)
+        }
+        ["when", value] => return when(value.parse()?, &read_input("input.txt")?),
+        ["when", value, path] => return when(value.parse()?, &read_input(path)?),
+        ["trace"] => return trace(&read_input("input.txt")?),
+        ["trace", path] => return trace(&read_input(path)?),
+        ["replay", trace] => return replay_file(trace, &read_input("input.txt")?),
+        ["replay", trace, path] => return replay_file(trace, &read_input(path)?),
+        ["run", path, ref options @ ..] => return run_firmware(path, options),
+        ["bench"] => return bench(200_000),
+        ["bench", len] => return bench(len.parse()?),
+        [] => (),
+        _ => bail!(
+            "usage: day8 [asm FILE | disasm [FILE] | debug [FILE] | cfg [--exact-end] [FILE] | dot [--exact-end] [FILE] | repairs K [--acc] [FILE] | when ACC [FILE] | trace [FILE] | replay TRACE [FILE] | run FILE [--max-steps N] [--timeout-ms N] [--exact-end] | bench [LEN]]"
+        ),
+    }
+
+    let instructions = read_input("input.txt")?;
     let mut processor = Processor::new();
     if let Terminate::InfiniteLoop(result) = processor.run(&instructions) {
         println!("part 1 result: {}", result);
     }
 
-    let result2 = brute_force(&mut instructions);
-    println!("part 2 result: {}", result2);
+    let repaired = repair(&instructions).ok_or_else(|| anyhow!("no fix found"))?;
+    println!(
+        "part 2 result: {} (patched {:04}: {} -> {})",
+        repaired.acc, repaired.address, repaired.old, repaired.new
+    );
 
     println!("Finished in {} us", start.elapsed().as_micros());
     Ok(())
@@ -129,9 +507,81 @@ fn main() -> Result<()> {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use Instruction::*;
+
+    /// The example boot code from the puzzle. It loops with acc 5, and ends with
+    /// acc 8 once the `jmp -4` at 7 becomes a `nop`.
+    pub fn example() -> Vec<Instruction> {
+        vec![
+            Nop(0),
+            Acc(1),
+            Jmp(4),
+            Acc(3),
+            Jmp(-3),
+            Acc(-99),
+            Acc(1),
+            Jmp(-4),
+            Acc(6),
+        ]
+    }
+
     #[test]
     fn parse() {
         assert_eq!("+3".parse::<i32>().unwrap(), 3);
         assert_eq!("-8".parse::<i32>().unwrap(), -8);
     }
+
+    #[test]
+    fn faults() {
+        let exact = Limits {
+            exact_end: true,
+            ..Limits::default()
+        };
+        assert_eq!(
+            Processor::new().run(&[Acc(1), Jmp(-2)]),
+            Terminate::Fault { acc: 1, target: -1 }
+        );
+        assert_eq!(
+            Processor::new().run(&[Acc(1), Jmp(5)]),
+            Terminate::EndOfCode(1)
+        );
+        let mut processor = Processor::with_limits(exact);
+        assert_eq!(
+            processor.run(&[Acc(1), Jmp(5)]),
+            Terminate::Fault { acc: 1, target: 6 }
+        );
+        assert_eq!(processor.prog_counter, 1);
+        assert_eq!(
+            Processor::with_limits(exact).run(&[Acc(1), Jmp(1)]),
+            Terminate::EndOfCode(1)
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let program: Vec<Op> = assemble_with("loop: acc +1\njmp loop").unwrap();
+        let mut processor = Processor::with_limits(Limits {
+            max_steps: Some(11),
+            ..Limits::default()
+        });
+        assert_eq!(processor.run(&program), Terminate::StepLimit(6));
+        assert_eq!(processor.prog_counter, 1);
+
+        // The clock is read before the first step and then every 4096 steps, so
+        // a zero timeout stops the program before it starts.
+        let mut processor = Processor::with_limits(Limits {
+            timeout: Some(Duration::ZERO),
+            ..Limits::default()
+        });
+        assert_eq!(processor.run(&program), Terminate::TimeLimit(0));
+        assert_eq!(processor.prog_counter, 0);
+
+        let mut processor = Processor::with_limits(Limits {
+            max_steps: Some(10_000),
+            timeout: Some(Duration::from_secs(3600)),
+            ..Limits::default()
+        });
+        assert_eq!(processor.run(&program), Terminate::StepLimit(5000));
+    }
 }
@@ -0,0 +1,322 @@
+//! Repairs boot code that loops by flipping a single `jmp` or `nop`, in time
+//! linear in the program size.
+//!
+//! Only a flip on the path the program actually executes can change its
+//! behaviour, and after the flip execution has to continue somewhere that
+//! already ends without any further change. So one backwards search finds every
+//! address that reaches the end, and one run along the original path finds the
+//! first flip that leads into that set.
+
+use crate::{Instruction, Processor, Terminate};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repair {
+    pub address: usize,
+    pub old: Instruction,
+    pub new: Instruction,
+    /// The accumulator once the repaired program ends.
+    pub acc: i32,
+}
+
+pub fn flip(instruction: Instruction) -> Option<Instruction> {
+    match instruction {
+        Instruction::Acc(_) => None,
+        Instruction::Jmp(n) => Some(Instruction::Nop(n)),
+        Instruction::Nop(n) => Some(Instruction::Jmp(n)),
+    }
+}
+
+/// Where execution continues after `instruction` at `addr`, which may be outside
+/// the program.
+fn successor(addr: usize, instruction: Instruction) -> i64 {
+    match instruction {
+        Instruction::Jmp(n) => addr as i64 + n as i64,
+        _ => addr as i64 + 1,
+    }
+}
+
+/// For every address, whether execution starting there reaches the end.
+pub fn terminating(instructions: &[Instruction]) -> Vec<bool> {
+    let len = instructions.len();
+    let mut predecessors = vec![Vec::new(); len];
+    let mut stack = Vec::new();
+    for (addr, instruction) in instructions.iter().enumerate() {
+        match successor(addr, *instruction) {
+            // Jumping before the start is a fault rather than an end.
+            next if next < 0 => (),
+            next if next >= len as i64 => stack.push(addr),
+            next => predecessors[next as usize].push(addr),
+        }
+    }
+
+    let mut terminating = vec![false; len];
+    while let Some(addr) = stack.pop() {
+        terminating[addr] = true;
+        stack.extend(&predecessors[addr]);
+    }
+    terminating
+}
+
+/// Finds the single flip that makes a looping program end. Returns `None` if no
+/// flip does, if the program already ends before any flip was needed, or if the
+/// repaired program overflows `acc`.
+pub fn repair(instructions: &[Instruction]) -> Option<Repair> {
+    // The argument below relies on the original run looping.
+    if !matches!(
+        Processor::new().run(instructions),
+        Terminate::InfiniteLoop(_)
+    ) {
+        return None;
+    }
+    let len = instructions.len();
+    let terminating = terminating(instructions);
+    let mut executed = vec![false; len];
+    let mut processor = Processor::new();
+
+    while processor.prog_counter < len && !executed[processor.prog_counter] {
+        let address = processor.prog_counter;
+        executed[address] = true;
+        let old = instructions[address];
+        if let Some(new) = flip(old) {
+            let next = successor(address, new);
+            if next >= len as i64 || (next >= 0 && terminating[next as usize]) {
+                // The rest of the run can't come back here, or it wouldn't end.
+                processor.step(new, len);
+                return match processor.run(instructions) {
+                    Terminate::EndOfCode(acc) => Some(Repair {
+                        address,
+                        old,
+                        new,
+                        acc,
+                    }),
+                    // Only `acc` overflowing can stop it otherwise.
+                    _ => None,
+                };
+            }
+        }
+        processor.step(old, len);
+    }
+    None
+}
+
+/// One changed instruction of a repair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    pub address: usize,
+    pub old: Instruction,
+    pub new: Instruction,
+}
+
+/// A set of changes that together make a program end, in the order they run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiRepair {
+    pub changes: Vec<Change>,
+    pub acc: i32,
+}
+
+/// Instructions `instruction` may have been before it was corrupted: a flipped
+/// `jmp`/`nop`, and with `change_acc`, an `acc` for either of them or the other
+/// way round. Operands stay the same.
+fn alternatives(instruction: Instruction, change_acc: bool) -> Vec<Instruction> {
+    let mut alternatives: Vec<Instruction> = flip(instruction).into_iter().collect();
+    if change_acc {
+        let n = instruction.operand();
+        match instruction {
+            Instruction::Acc(_) => alternatives.extend([Instruction::Jmp(n), Instruction::Nop(n)]),
+            _ => alternatives.push(Instruction::Acc(n)),
+        }
+    }
+    alternatives
+}
+
+struct Search<'a> {
+    program: Vec<Instruction>,
+    terminating: &'a [bool],
+    executed: Vec<bool>,
+    changes: Vec<Change>,
+    change_acc: bool,
+    repairs: Vec<MultiRepair>,
+}
+
+impl Search<'_> {
+    /// Runs on from `processor` and tries every change along the way, with up to
+    /// `remaining` changes left.
+    fn run(&mut self, mut processor: Processor, remaining: usize) {
+        let len = self.program.len();
+        let mut marked = Vec::new();
+        while processor.prog_counter < len && !self.executed[processor.prog_counter] {
+            let address = processor.prog_counter;
+            self.executed[address] = true;
+            marked.push(address);
+            let old = self.program[address];
+
+            for new in alternatives(old, self.change_acc) {
+                let next = successor(address, new);
+                // A change that keeps the path the same can't end the loop, and
+                // jumping before the start is a fault.
+                if next == successor(address, old) || next < 0 {
+                    continue;
+                }
+                let mut changed = processor.clone();
+                if changed.step(new, len).is_some() {
+                    continue;
+                }
+                self.program[address] = new;
+                self.changes.push(Change { address, old, new });
+                if next >= len as i64 || self.terminating[next as usize] {
+                    // As in `repair`, the rest of the run can't come back to
+                    // anything executed so far, so this is the whole repair.
+                    if let Terminate::EndOfCode(acc) = changed.run(&self.program) {
+                        self.repairs.push(MultiRepair {
+                            changes: self.changes.clone(),
+                            acc,
+                        });
+                    }
+                } else if remaining > 1 {
+                    // The change doesn't alter which addresses reach the end: the
+                    // loop it leads into has to be broken by a later change.
+                    self.run(changed, remaining - 1);
+                }
+                self.changes.pop();
+                self.program[address] = old;
+            }
+
+            if processor.step(old, len).is_some() {
+                break;
+            }
+        }
+        for address in marked {
+            self.executed[address] = false;
+        }
+    }
+}
+
+/// Every repair of at most `max_changes` changes that makes `instructions` end,
+/// where each change is on the path the program takes and is needed. Without
+/// `change_acc` only `jmp` and `nop` are flipped.
+pub fn repairs(
+    instructions: &[Instruction],
+    max_changes: usize,
+    change_acc: bool,
+) -> Vec<MultiRepair> {
+    let terminating = terminating(instructions);
+    let mut search = Search {
+        program: instructions.to_vec(),
+        terminating: &terminating,
+        executed: vec![false; instructions.len()],
+        changes: Vec::new(),
+        change_acc,
+        repairs: Vec::new(),
+    };
+    if max_changes > 0 {
+        search.run(Processor::new(), max_changes);
+    }
+    search.repairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::example;
+    use crate::{brute_force, synthetic_program};
+    use Instruction::*;
+
+    #[test]
+    fn puzzle_example() {
+        let program = example();
+        assert_eq!(
+            terminating(&program),
+            vec![false, false, false, false, false, false, false, false, true]
+        );
+        assert_eq!(
+            repair(&program),
+            Some(Repair {
+                address: 7,
+                old: Jmp(-4),
+                new: Nop(-4),
+                acc: 8
+            })
+        );
+        assert_eq!(repair(&[Jmp(0), Jmp(0)]), None);
+        // Already ends, and flipping the first nop would make it loop.
+        assert_eq!(repair(&[Nop(2), Jmp(3), Jmp(-2)]), None);
+    }
+
+    #[test]
+    fn agrees_with_brute_force() {
+        for seed in 1..20 {
+            let program = synthetic_program(500, seed);
+            let repaired = repair(&program).unwrap();
+            assert_eq!(
+                Some(repaired.acc),
+                brute_force(&mut program.clone()),
+                "seed {}",
+                seed
+            );
+            // The first repair on the path is the one `repair` finds.
+            assert_eq!(
+                repairs(&program, 1, false)[0],
+                MultiRepair {
+                    changes: vec![Change {
+                        address: repaired.address,
+                        old: repaired.old,
+                        new: repaired.new
+                    }],
+                    acc: repaired.acc
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn two_changes() {
+        let program = vec![Nop(3), Acc(1), Jmp(-2), Acc(2), Nop(2), Jmp(-5)];
+        assert_eq!(repair(&program), None);
+        assert!(repairs(&program, 1, true).is_empty());
+
+        let change = |address, new| Change {
+            address,
+            old: program[address],
+            new,
+        };
+        let found = repairs(&program, 2, false);
+        assert_eq!(
+            found,
+            vec![
+                MultiRepair {
+                    changes: vec![change(0, Jmp(3)), change(4, Jmp(2))],
+                    acc: 2
+                },
+                MultiRepair {
+                    changes: vec![change(0, Jmp(3)), change(5, Nop(-5))],
+                    acc: 2
+                },
+                MultiRepair {
+                    changes: vec![change(2, Nop(-2)), change(4, Jmp(2))],
+                    acc: 3
+                },
+                MultiRepair {
+                    changes: vec![change(2, Nop(-2)), change(5, Nop(-5))],
+                    acc: 3
+                },
+            ]
+        );
+
+        let with_acc = repairs(&program, 2, true);
+        assert_eq!(with_acc.len(), 9);
+        assert!(with_acc.contains(&MultiRepair {
+            changes: vec![change(2, Acc(-2)), change(5, Acc(-5))],
+            acc: -4
+        }));
+        for repair in with_acc {
+            let mut patched = program.clone();
+            for change in &repair.changes {
+                patched[change.address] = change.new;
+            }
+            assert_eq!(
+                Processor::new().run(&patched),
+                Terminate::EndOfCode(repair.acc)
+            );
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Execution traces: one JSON object per executed instruction, such as
+//! `{"pc":2,"instruction":"acc +3","acc_before":1,"acc_after":4}`.
+
+use crate::asm::assemble;
+use crate::{Instruction, Processor, Terminate};
+use std::convert::TryFrom;
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceStep<I = Instruction> {
+    pub pc: usize,
+    pub instruction: I,
+    pub acc_before: i32,
+    pub acc_after: i32,
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}: {} (acc {} -> {})",
+            self.pc, self.instruction, self.acc_before, self.acc_after
+        )
+    }
+}
+
+/// Receives every instruction `Processor::run_traced` executes.
+pub trait Tracer<I> {
+    fn record(&mut self, step: TraceStep<I>);
+}
+
+/// Records nothing, which is what a plain `run` uses.
+impl<I> Tracer<I> for () {
+    fn record(&mut self, _step: TraceStep<I>) {}
+}
+
+impl<I> Tracer<I> for Vec<TraceStep<I>> {
+    fn record(&mut self, step: TraceStep<I>) {
+        self.push(step);
+    }
+}
+
+pub fn to_json_lines(trace: &[TraceStep]) -> String {
+    let mut text = String::new();
+    for step in trace {
+        writeln!(
+            text,
+            "{{\"pc\":{},\"instruction\":\"{}\",\"acc_before\":{},\"acc_after\":{}}}",
+            step.pc, step.instruction, step.acc_before, step.acc_after
+        )
+        .unwrap();
+    }
+    text
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "trace line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+enum Value<'a> {
+    Number(i64),
+    String(&'a str),
+}
+
+/// Splits a flat JSON object into its keys and values. Strings may not contain
+/// escapes, which a trace never needs.
+fn fields(line: &str) -> Result<Vec<(&str, Value<'_>)>, String> {
+    let inner = line
+        .trim()
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+        .ok_or("expected a JSON object")?;
+    let string = |text: &str| -> Result<(usize, usize), String> {
+        let text = text.trim_start();
+        match text.strip_prefix('"').and_then(|rest| rest.find('"')) {
+            Some(end) => Ok((1, end + 1)),
+            None => Err(format!("expected a string at {:?}", text)),
+        }
+    };
+
+    let mut fields = Vec::new();
+    let mut rest = inner.trim();
+    while !rest.is_empty() {
+        let (start, end) = string(rest)?;
+        let key = &rest[start..end];
+        rest = rest[end + 1..].trim_start();
+        rest = rest
+            .strip_prefix(':')
+            .ok_or_else(|| format!("expected ':' after {:?}", key))?
+            .trim_start();
+        let (value, len) = if rest.starts_with('"') {
+            let (start, end) = string(rest)?;
+            (Value::String(&rest[start..end]), end + 1)
+        } else {
+            let len = rest.find(',').unwrap_or(rest.len());
+            let number = rest[..len].trim();
+            match number.parse() {
+                Ok(n) => (Value::Number(n), len),
+                Err(_) => return Err(format!("invalid value {:?} for {:?}", number, key)),
+            }
+        };
+        fields.push((key, value));
+        rest = rest[len..].trim_start();
+        if let Some(next) = rest.strip_prefix(',') {
+            rest = next.trim_start();
+        } else if !rest.is_empty() {
+            return Err(format!("expected ',' at {:?}", rest));
+        }
+    }
+    Ok(fields)
+}
+
+fn parse_step(line: &str) -> Result<TraceStep, String> {
+    let (mut pc, mut instruction, mut acc_before, mut acc_after) = (None, None, None, None);
+    for (key, value) in fields(line)? {
+        match (key, value) {
+            ("pc", Value::Number(n)) => {
+                pc = Some(usize::try_from(n).map_err(|_| format!("invalid pc {}", n))?)
+            }
+            ("instruction", Value::String(text)) => {
+                instruction = match assemble(text).map_err(|err| err.message)?[..] {
+                    [instruction] => Some(instruction),
+                    _ => return Err(format!("expected one instruction, found {:?}", text)),
+                }
+            }
+            ("acc_before", Value::Number(n)) => {
+                acc_before = Some(i32::try_from(n).map_err(|_| format!("invalid acc {}", n))?)
+            }
+            ("acc_after", Value::Number(n)) => {
+                acc_after = Some(i32::try_from(n).map_err(|_| format!("invalid acc {}", n))?)
+            }
+            (key, _) => return Err(format!("unexpected field {:?}", key)),
+        }
+    }
+    match (pc, instruction, acc_before, acc_after) {
+        (Some(pc), Some(instruction), Some(acc_before), Some(acc_after)) => Ok(TraceStep {
+            pc,
+            instruction,
+            acc_before,
+            acc_after,
+        }),
+        _ => Err(String::from(
+            "expected pc, instruction, acc_before and acc_after",
+        )),
+    }
+}
+
+pub fn parse_json_lines(text: &str) -> Result<Vec<TraceStep>, TraceError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(line_nr, line)| {
+            parse_step(line).map_err(|message| TraceError {
+                line: line_nr + 1,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// The first step at which a replayed run differs from its trace. `None` on
+/// either side means that run had already terminated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: usize,
+    pub expected: Option<TraceStep>,
+    pub actual: Option<TraceStep>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |step: &Option<TraceStep>| match step {
+            Some(step) => step.to_string(),
+            None => String::from("termination"),
+        };
+        write!(
+            f,
+            "divergence at step {}: trace has {}, replay has {}",
+            self.step,
+            show(&self.expected),
+            show(&self.actual)
+        )
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+struct Replayer<'a> {
+    expected: &'a [TraceStep],
+    step: usize,
+    divergence: Option<Divergence>,
+}
+
+impl Tracer<Instruction> for Replayer<'_> {
+    fn record(&mut self, step: TraceStep) {
+        let expected = self.expected.get(self.step).copied();
+        if self.divergence.is_none() && expected != Some(step) {
+            self.divergence = Some(Divergence {
+                step: self.step,
+                expected,
+                actual: Some(step),
+            });
+        }
+        self.step += 1;
+    }
+}
+
+/// Re-runs `instructions` and checks that every step matches `trace`.
+pub fn replay(instructions: &[Instruction], trace: &[TraceStep]) -> Result<Terminate, Divergence> {
+    let mut replayer = Replayer {
+        expected: trace,
+        step: 0,
+        divergence: None,
+    };
+    let result = Processor::new().run_traced(instructions, &mut replayer);
+    match replayer.divergence {
+        Some(divergence) => Err(divergence),
+        None if replayer.step < trace.len() => Err(Divergence {
+            step: replayer.step,
+            expected: Some(trace[replayer.step]),
+            actual: None,
+        }),
+        None => Ok(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::example;
+    use Instruction::*;
+
+    #[test]
+    fn json_lines() {
+        let mut trace = Vec::new();
+        let result = Processor::new().run_traced(&example(), &mut trace);
+        assert_eq!(result, Terminate::InfiniteLoop(5));
+        assert_eq!(trace.len(), 7);
+
+        let text = to_json_lines(&trace);
+        assert!(text.starts_with(
+            "{\"pc\":0,\"instruction\":\"nop +0\",\"acc_before\":0,\"acc_after\":0}\n\
+             {\"pc\":1,\"instruction\":\"acc +1\",\"acc_before\":0,\"acc_after\":1}\n"
+        ));
+        assert_eq!(parse_json_lines(&text).unwrap(), trace);
+        assert_eq!(
+            parse_json_lines(
+                r#" { "acc_after" : 4, "instruction": "acc +3", "pc": 3, "acc_before": 1 } "#
+            )
+            .unwrap(),
+            vec![TraceStep {
+                pc: 3,
+                instruction: Acc(3),
+                acc_before: 1,
+                acc_after: 4
+            }]
+        );
+        assert_eq!(
+            parse_json_lines("\n{\"pc\":-1}").unwrap_err().to_string(),
+            "trace line 2: invalid pc -1"
+        );
+    }
+
+    #[test]
+    fn divergence() {
+        let mut program = example();
+        let mut trace = Vec::new();
+        Processor::new().run_traced(&program, &mut trace);
+        assert_eq!(replay(&program, &trace), Ok(Terminate::InfiniteLoop(5)));
+
+        program[3] = Acc(2);
+        let divergence = replay(&program, &trace).unwrap_err();
+        assert_eq!(divergence.step, 5);
+        assert_eq!(
+            divergence.to_string(),
+            "divergence at step 5: trace has 0003: acc +3 (acc 2 -> 5), \
+             replay has 0003: acc +2 (acc 2 -> 4)"
+        );
+
+        program[3] = Acc(3);
+        program[7] = Nop(-4);
+        let divergence = replay(&program, &trace).unwrap_err();
+        assert_eq!((divergence.step, divergence.expected), (4, Some(trace[4])));
+
+        let divergence = replay(&example(), &trace[..6]).unwrap_err();
+        assert_eq!((divergence.step, divergence.expected), (6, None));
+    }
+}
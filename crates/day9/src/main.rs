@@ -1,4 +1,5 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
@@ -16,13 +17,13 @@ impl Window {
     }
 
     fn find_pair_for(&self, sum: u64) -> Option<(u64, u64)> {
-        for i in 0..25 {
-            for j in 0..25 {
+        for (i, a) in self.numbers.iter().enumerate() {
+            for (j, b) in self.numbers.iter().enumerate() {
                 if i == j {
                     continue;
                 }
-                if self.numbers[i] + self.numbers[j] == sum {
-                    return Some((self.numbers[i], self.numbers[j]));
+                if a + b == sum {
+                    return Some((*a, *b));
                 }
             }
         }
@@ -35,42 +36,50 @@ impl Window {
     }
 }
 
-fn validate_xmas(numbers: &[u64]) -> u64 {
-    let mut window = Window::new(&numbers[0..25]);
+/// Finds the first number that isn't the sum of two of the `preamble` numbers
+/// before it.
+fn validate_xmas(numbers: &[u64], preamble: usize) -> Result<u64> {
+    if preamble < 2 {
+        bail!("preamble must be at least 2 numbers, got {}", preamble);
+    }
+    if numbers.len() <= preamble {
+        bail!(
+            "input has {} numbers, needs more than the preamble of {}",
+            numbers.len(),
+            preamble
+        );
+    }
+    let mut window = Window::new(&numbers[..preamble]);
 
-    for i in 25..numbers.len() {
-        match window.find_pair_for(numbers[i]) {
+    for &number in &numbers[preamble..] {
+        match window.find_pair_for(number) {
             Some(_) => (),
-            None => return numbers[i],
+            None => return Ok(number),
         }
-        window.advance(numbers[i]);
+        window.advance(number);
     }
-    panic!("no invalid number found")
+    bail!("no invalid number found")
 }
 
-fn find_weakness(numbers: &[u64], sum: u64) -> u64 {
+/// Finds a contiguous range of at least two numbers adding up to `sum` and
+/// returns the sum of its smallest and largest number.
+fn find_weakness(numbers: &[u64], sum: u64) -> Result<u64> {
     let mut range: Vec<u64> = Vec::new();
     for num in numbers {
         range.push(*num);
-        let curr_sum: u64 = range.iter().sum();
+        let mut curr_sum: u64 = range.iter().sum();
+        while curr_sum > sum {
+            curr_sum -= range.remove(0);
+        }
         if curr_sum == sum && range.len() >= 2 {
-            break;
-        } else if curr_sum > sum {
-            let mut new_sum = curr_sum;
-            while new_sum > sum {
-                new_sum -= range.remove(0);
-            }
-            if new_sum == sum {
-                break;
-            }
+            return Ok(range.iter().max().unwrap() + range.iter().min().unwrap());
         }
     }
-    println!("range: {:?}", range);
-    range.iter().max().unwrap() + range.iter().min().unwrap()
+    bail!("no contiguous range adds up to {}", sum)
 }
 
-fn read_input() -> Result<Vec<u64>> {
-    let file = File::open("input.txt")?;
+fn read_input(path: &str) -> Result<Vec<u64>> {
+    let file = File::open(path)?;
     let reader = BufReader::new(file);
 
     let numbers: Vec<u64> = reader
@@ -85,12 +94,54 @@ fn read_input() -> Result<Vec<u64>> {
 fn main() -> Result<()> {
     let start = Instant::now();
 
-    let numbers = read_input()?;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (preamble, path) = match args[..] {
+        [] => (25, "input.txt"),
+        ["--preamble", preamble] => (preamble.parse()?, "input.txt"),
+        ["--preamble", preamble, path] => (preamble.parse()?, path),
+        [path] => (25, path),
+        _ => bail!("usage: day9 [--preamble N] [FILE]"),
+    };
+    let numbers = read_input(path)?;
 
-    let result1 = validate_xmas(&numbers);
+    let result1 = validate_xmas(&numbers, preamble)?;
     println!("part 1 result: {}", result1);
-    println!("part 2 result: {}", find_weakness(&numbers, result1));
+    println!("part 2 result: {}", find_weakness(&numbers, result1)?);
 
     println!("Finished in {} us", start.elapsed().as_micros());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [u64; 20] = [
+        35, 20, 15, 25, 47, 40, 62, 55, 65, 95, 102, 117, 150, 182, 127, 219, 299, 277, 309, 576,
+    ];
+
+    #[test]
+    fn example() {
+        assert_eq!(validate_xmas(&EXAMPLE, 5).unwrap(), 127);
+        assert_eq!(find_weakness(&EXAMPLE, 127).unwrap(), 62);
+    }
+
+    #[test]
+    fn short_input() {
+        assert_eq!(
+            validate_xmas(&EXAMPLE[..5], 5).unwrap_err().to_string(),
+            "input has 5 numbers, needs more than the preamble of 5"
+        );
+        assert!(validate_xmas(&EXAMPLE, 25).is_err());
+        assert!(validate_xmas(&EXAMPLE[..14], 5).is_err());
+    }
+
+    #[test]
+    fn no_weakness() {
+        assert!(find_weakness(&[], 127).is_err());
+        assert!(find_weakness(&[127], 127).is_err());
+        assert!(find_weakness(&EXAMPLE[..5], 127).is_err());
+        assert_eq!(find_weakness(&[200, 100, 27], 127).unwrap(), 127);
+    }
+}